@@ -6,8 +6,8 @@ use rand::prelude::*;
 use rayon::prelude::*;
 
 use flatland::{
-    grid::GridLike,
-    simulation::{config::Config, Element, State},
+    grid::{Anchor, EdgePolicy, GridLike},
+    simulation::{config::Config, error::SimulationError, State, Tile},
 };
 use serde::{Deserialize, Serialize};
 use statistical::{mean, standard_deviation};
@@ -36,7 +36,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         BinaryHeap::new()
     };
     loop {
-        let config_scores = score_configs(&configs);
+        let config_scores = score_configs(&configs, rng.gen());
         let scores: Vec<_> = config_scores
             .iter()
             .take(config_scores.len() / 2)
@@ -104,7 +104,7 @@ fn next_diversify_generation(
         let sorted_elites = elites.clone().into_sorted_vec();
         new_configs.extend(
             sorted_elites
-                .choose_multiple_weighted(&mut rng, 2, |cs| cs.1 .0)
+                .choose_multiple_weighted(&mut rng, 2, |cs| weight(cs.1 .0))
                 .unwrap()
                 .map(|x| x.0.clone()),
         );
@@ -125,7 +125,7 @@ fn next_incremental_generation(configs_scores: &[ConfigScore]) -> Vec<Config> {
             .choose_multiple_weighted(
                 &mut rng,
                 (configs_scores.len() - 2) / 2,
-                |ConfigScore(_, s)| s.0,
+                |ConfigScore(_, s)| weight(s.0),
             )
             .unwrap()
             .map(|x| {
@@ -137,7 +137,7 @@ fn next_incremental_generation(configs_scores: &[ConfigScore]) -> Vec<Config> {
     new_configs.push(Config::gen(&mut rng));
     for _ in 0..((configs_scores.len() - 2) / 2) {
         let mut competitors = configs_scores
-            .choose_multiple_weighted(&mut rng, 2, |ConfigScore(_, s)| s.0)
+            .choose_multiple_weighted(&mut rng, 2, |ConfigScore(_, s)| weight(s.0))
             .unwrap();
         let a = competitors.next().unwrap().0.clone();
         let b = competitors.next().unwrap().0.clone();
@@ -148,22 +148,40 @@ fn next_incremental_generation(configs_scores: &[ConfigScore]) -> Vec<Config> {
     new_configs
 }
 
-fn score_configs(configs: &[Config]) -> Vec<ConfigScore> {
+/// How many updates every config is scored over.
+const SCORED_UPDATES: usize = 100;
+
+/// The score of a config whose simulation fails. [`score_state`] never goes below -2, since only
+/// its distribution and pattern scores can be negative and neither goes below -1, so this is
+/// below anything a config that runs can reach.
+const FAILED_SCORE: f32 = -2.0 * SCORED_UPDATES as f32;
+
+/// The weight a config with `score` is sampled with when picking parents. Scores can be negative,
+/// so they are shifted to make the weight of a failed config zero and every other weight positive.
+fn weight(score: f32) -> f32 {
+    score - FAILED_SCORE
+}
+
+/// Scores every config on the same world, generated from `seed`, so that fitness differences
+/// come from the configs rather than from the starting conditions. Configs whose simulation fails
+/// get [`FAILED_SCORE`], which ranks them last and keeps them from being picked as parents.
+fn score_configs(configs: &[Config], seed: u64) -> Vec<ConfigScore> {
     let mut config_score: Vec<ConfigScore> = configs
         .par_iter()
         .map(|c| {
-            let mut state = State::gen(c.clone(), 64, 64);
-            ConfigScore(
-                c.clone(),
-                (0..100)
-                    .map(|_| {
-                        let old_state = state.clone();
-                        state.update();
-                        score_state(&old_state, &state)
-                    })
-                    .sum::<f32>()
-                    .into(),
-            )
+            let mut state = State::gen_with_seed(c.clone(), 64, 64, seed);
+            let score = (0..SCORED_UPDATES)
+                .map(|_| {
+                    let old_state = state.clone();
+                    state.update()?;
+                    Ok(score_state(&old_state, &state))
+                })
+                .sum::<Result<f32, SimulationError>>()
+                .unwrap_or_else(|e| {
+                    eprintln!("Config failed: {e}");
+                    FAILED_SCORE
+                });
+            ConfigScore(c.clone(), score.into())
         })
         .collect();
 
@@ -187,13 +205,14 @@ fn score_state(old_state: &State, state: &State) -> f32 {
     let position_score = state
         .elements
         .enumerate()
-        .map(|(_x, y, t)| match t.element() {
-            Element::Air => (state.elements.height() - y) as f32 / state.elements.height() as f32,
-            Element::Soil => y as f32 / state.elements.height() as f32,
-            Element::Water => {
+        .map(|(_x, y, t)| match Kind::of(state, t) {
+            Kind::Air => (state.elements.height() - y) as f32 / state.elements.height() as f32,
+            Kind::Soil => y as f32 / state.elements.height() as f32,
+            Kind::Water => {
                 ((state.elements.height() / 2) as f32 - y as f32).abs()
                     / (state.elements.height() / 2) as f32
             }
+            Kind::Other => 0.0,
         })
         .sum::<f32>()
         / element_count as f32;
@@ -203,7 +222,7 @@ fn score_state(old_state: &State, state: &State) -> f32 {
         - (state
             .elements
             .iter()
-            .filter(|t| t.element() == Element::Air)
+            .filter(|t| Kind::of(state, t) == Kind::Air)
             .count() as f32
             - one_third_count)
             .abs())
@@ -212,7 +231,7 @@ fn score_state(old_state: &State, state: &State) -> f32 {
             - (state
                 .elements
                 .iter()
-                .filter(|t| t.element() == Element::Water)
+                .filter(|t| Kind::of(state, t) == Kind::Water)
                 .count() as f32
                 - one_third_count)
                 .abs())
@@ -221,20 +240,20 @@ fn score_state(old_state: &State, state: &State) -> f32 {
 
     let pattern_score = state
         .elements
-        .windows(3)
+        .windows(3, Anchor::Center, EdgePolicy::Skip)
         .map(|w| {
-            use Element::*;
+            use Kind::*;
 
             match (
-                w.get(-1, -1).map(|t| t.element()),
-                w.get(0, -1).map(|t| t.element()),
-                w.get(1, -1).map(|t| t.element()),
-                w.get(-1, 0).map(|t| t.element()),
-                w.get(0, 0).map(|t| t.element()),
-                w.get(1, 0).map(|t| t.element()),
-                w.get(-1, 1).map(|t| t.element()),
-                w.get(0, 1).map(|t| t.element()),
-                w.get(1, 1).map(|t| t.element()),
+                w.get(-1, -1).map(|t| Kind::of(state, t)),
+                w.get(0, -1).map(|t| Kind::of(state, t)),
+                w.get(1, -1).map(|t| Kind::of(state, t)),
+                w.get(-1, 0).map(|t| Kind::of(state, t)),
+                w.get(0, 0).map(|t| Kind::of(state, t)),
+                w.get(1, 0).map(|t| Kind::of(state, t)),
+                w.get(-1, 1).map(|t| Kind::of(state, t)),
+                w.get(0, 1).map(|t| Kind::of(state, t)),
+                w.get(1, 1).map(|t| Kind::of(state, t)),
             ) {
                 (
                     Some(Water | Air),
@@ -404,3 +423,24 @@ fn score_state(old_state: &State, state: &State) -> f32 {
         + pattern_score
         + conflict_score
 }
+
+/// The elements the fitness function knows how to score, looked up by name so that configs with
+/// extra elements can still be scored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Air,
+    Soil,
+    Water,
+    Other,
+}
+
+impl Kind {
+    fn of(state: &State, t: &Tile) -> Self {
+        match state.config.elements.get(t.element()).name.as_str() {
+            "air" => Kind::Air,
+            "soil" => Kind::Soil,
+            "water" => Kind::Water,
+            _ => Kind::Other,
+        }
+    }
+}
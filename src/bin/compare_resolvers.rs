@@ -0,0 +1,92 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use flatland::simulation::{
+    config::Config,
+    conflict::Resolver,
+    error::SimulationError,
+    observer::{Phase, SimulationEvent, SimulationObserver},
+    State,
+};
+
+const RESOLVERS: [Resolver; 2] = [Resolver::Greedy, Resolver::Auction];
+const SEEDS: u64 = 4;
+const TICKS: usize = 50;
+
+/// What one resolver did across every tick it was given.
+#[derive(Default)]
+struct Totals {
+    ticks: usize,
+    movement: Duration,
+    alignment: f64,
+    iterations: usize,
+    bids: usize,
+}
+
+impl SimulationObserver for Totals {
+    fn on_event(&mut self, event: &SimulationEvent) {
+        match event {
+            SimulationEvent::ConflictResolution {
+                iterations,
+                bids,
+                alignment,
+            } => {
+                self.ticks += 1;
+                self.iterations += iterations;
+                self.bids += bids;
+                self.alignment += *alignment as f64;
+            }
+            SimulationEvent::PhaseTiming {
+                phase: Phase::Movement,
+                elapsed,
+            } => self.movement += *elapsed,
+            _ => {}
+        }
+    }
+}
+
+/// Gives every resolver the same worlds, tick after tick, and compares how long they take to move
+/// the tiles and how well the moves they pick line up with the forces on the tiles. The worlds
+/// themselves advance with the default config, so every resolver always starts from the same
+/// state.
+fn main() -> Result<(), SimulationError> {
+    let totals: Vec<_> = RESOLVERS
+        .iter()
+        .map(|_| Arc::new(Mutex::new(Totals::default())))
+        .collect();
+
+    for seed in 0..SEEDS {
+        let mut state = State::gen_with_seed(Config::default(), 128, 96, seed);
+        for _ in 0..TICKS {
+            for (&resolver, totals) in RESOLVERS.iter().zip(totals.iter()) {
+                let mut trial = state.clone();
+                trial.config.resolver = resolver;
+                trial.add_observer(totals.clone());
+                trial.update()?;
+            }
+            state.update()?;
+        }
+    }
+
+    println!(
+        "{:<10} {:>16} {:>16} {:>16} {:>16}",
+        "resolver", "movement (ms)", "alignment", "iterations", "bids"
+    );
+    for (resolver, totals) in RESOLVERS.iter().zip(totals.iter()) {
+        let totals = totals.lock().unwrap();
+        let ticks = totals.ticks.max(1) as f64;
+        println!(
+            "{:<10} {:>16.3} {:>16.1} {:>16.1} {:>16.1}",
+            format!("{resolver:?}"),
+            totals.movement.as_secs_f64() * 1000.0 / ticks,
+            totals.alignment / ticks,
+            totals.iterations as f64 / ticks,
+            totals.bids as f64 / ticks,
+        );
+    }
+    println!("Averages per tick.");
+
+    Ok(())
+}
@@ -10,7 +10,7 @@ use show_image::{
 };
 use step_ranker::Ranker;
 
-use flatland::simulation::{config::Config, State};
+use flatland::simulation::{config::Config, error::SimulationError, State};
 
 #[show_image::main]
 fn main() -> Result<(), Box<dyn Error>> {
@@ -62,10 +62,11 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         Ok(())
     };
-    let update_state = |states: &mut [State]| {
+    let update_state = |states: &mut [State]| -> Result<(), SimulationError> {
         for state in states {
-            state.update();
+            state.update()?;
         }
+        Ok(())
     };
 
     update_image(&states)?;
@@ -79,7 +80,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
                 match event.input.key_code {
                     Some(VirtualKeyCode::Escape) => return Ok(()),
-                    Some(VirtualKeyCode::Space) if !running => update_state(&mut states),
+                    Some(VirtualKeyCode::Space) if !running => update_state(&mut states)?,
                     Some(VirtualKeyCode::S) => running = !running,
                     Some(VirtualKeyCode::Left) => {
                         rank_selected(Ordering::Greater, &mut competitors, &mut selected);
@@ -116,7 +117,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 update_image(&states)?;
             }
             Err(TryRecvError::Empty) if running => {
-                update_state(&mut states);
+                update_state(&mut states)?;
                 update_image(&states)?;
             }
             Err(TryRecvError::Disconnected) => return Ok(()),
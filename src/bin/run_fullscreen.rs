@@ -39,14 +39,18 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
                 match event.input.key_code {
                     Some(VirtualKeyCode::Escape) => return Ok(()),
-                    Some(VirtualKeyCode::Space) if !running => update_state(&mut state),
+                    Some(VirtualKeyCode::Space) if !running => update_state(&mut state)?,
                     Some(VirtualKeyCode::S) => running = !running,
+                    Some(VirtualKeyCode::P) => state.save_binary(File::create("snapshot.bin")?)?,
+                    Some(VirtualKeyCode::L) => {
+                        state = State::load_binary(File::open("snapshot.bin")?)?
+                    }
                     _ => continue,
                 }
                 update_image(&state)?;
             }
             Err(TryRecvError::Empty) if running => {
-                update_state(&mut state);
+                update_state(&mut state)?;
                 update_image(&state)?;
             }
             Err(TryRecvError::Disconnected) => return Ok(()),
@@ -1,8 +1,8 @@
-use flatland::simulation::{config::Config, State};
+use flatland::simulation::{config::Config, error::SimulationError, State};
 
-fn main() {
+fn main() -> Result<(), SimulationError> {
     let mut state: State = State::gen(Config::default(), 320, 180);
     loop {
-        state.update();
+        state.update()?;
     }
 }
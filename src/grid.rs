@@ -1,38 +1,81 @@
-use std::marker::PhantomData;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub mod chunked;
+mod edit;
+pub mod neighborhood;
+pub mod topology;
+pub mod traversal;
 
 pub trait GridLike<T> {
     fn get(&self, x: isize, y: isize) -> Option<&T>;
     fn height(&self) -> usize;
     fn width(&self) -> usize;
 
-    fn windows(&self, size: usize) -> GridWindows<'_, T, Self>
+    /// A window around every cell, in row-major order.
+    fn windows(&self, size: usize, anchor: Anchor, edges: EdgePolicy<T>) -> GridWindows<'_, T, Self>
     where
         Self: Sized,
+        T: Clone,
     {
-        GridWindows::new(self, size)
+        GridWindows {
+            grid: self,
+            enumerator: GridEnumerator::new(self),
+            size,
+            anchor,
+            edges,
+        }
     }
 
-    fn window_at(&self, size: usize, (x, y): (usize, usize)) -> GridWindow<'_, T, Self>
+    /// A `size` x `size` window onto this grid, positioned relative to the cell at `(x, y)` by
+    /// `anchor`.
+    fn window_at(
+        &self,
+        (x, y): (usize, usize),
+        size: usize,
+        anchor: Anchor,
+        edges: EdgePolicy<T>,
+    ) -> GridWindow<'_, T, Self>
     where
         Self: Sized,
     {
-        GridWindow {
-            grid: self,
-            x,
-            y,
-            size,
-            _p: PhantomData,
-        }
+        GridWindow::new(self, (x, y), size, anchor, edges)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "GridCells<T>")]
 pub struct Grid<T> {
     width: usize,
     height: usize,
     cells: Vec<T>,
 }
 
+/// A grid as it is read from a file, before its size has been checked against its cells.
+#[derive(Deserialize)]
+struct GridCells<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+impl<T> TryFrom<GridCells<T>> for Grid<T> {
+    type Error = String;
+
+    fn try_from(grid: GridCells<T>) -> Result<Self, Self::Error> {
+        if grid.width.checked_mul(grid.height) != Some(grid.cells.len()) {
+            return Err(format!(
+                "A {}x{} grid should not have {} cells",
+                grid.width,
+                grid.height,
+                grid.cells.len()
+            ));
+        }
+        Ok(Grid::from_cells(grid.width, grid.height, grid.cells))
+    }
+}
+
 impl<T> Grid<T> {
     pub fn new(width: usize, height: usize, init: impl Fn(usize, usize) -> T + Copy) -> Self {
         Self {
@@ -69,12 +112,114 @@ impl<T> Grid<T> {
         })
     }
 
+    pub fn enumerate_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut T)> {
+        let width = self.width;
+        self.cells
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, t)| (i % width, i / width, t))
+    }
+
     pub fn get_mut(&mut self, x: isize, y: isize) -> Option<&mut T> {
         if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
             return None;
         }
         self.cells.get_mut(x as usize + self.width * y as usize)
     }
+
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Grid<U> {
+        Grid::from_cells(self.width, self.height, self.cells.iter().map(f).collect())
+    }
+
+    /// Combines two grids of the same size cell by cell.
+    pub fn zip_with<U, V>(&self, other: &Grid<U>, mut f: impl FnMut(&T, &U) -> V) -> Grid<V> {
+        assert_eq!(
+            (self.width, self.height),
+            (other.width, other.height),
+            "Zipped grids should be the same size"
+        );
+        Grid::from_cells(
+            self.width,
+            self.height,
+            self.cells
+                .iter()
+                .zip(other.cells.iter())
+                .map(|(a, b)| f(a, b))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Sync> Grid<T> {
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = &T> {
+        self.cells.par_iter()
+    }
+
+    pub fn par_enumerate(&self) -> impl IndexedParallelIterator<Item = (usize, usize, &T)> {
+        let width = self.width;
+        self.cells
+            .par_iter()
+            .enumerate()
+            .map(move |(i, t)| (i % width, i / width, t))
+    }
+
+    /// Like [`Grid::map`], but maps cells in parallel.
+    pub fn par_map<U: Send>(&self, f: impl Fn(&T) -> U + Sync + Send) -> Grid<U> {
+        Grid::from_cells(self.width, self.height, self.cells.par_iter().map(f).collect())
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Send> Grid<T> {
+    /// Like [`Grid::new`], but initializes cells in parallel. Each cell is computed on its own,
+    /// so the result does not depend on the number of threads.
+    pub fn par_new(width: usize, height: usize, init: impl Fn(usize, usize) -> T + Sync) -> Self {
+        Self {
+            width,
+            height,
+            cells: (0..width * height)
+                .into_par_iter()
+                .map(|i| init(i % width, i / width))
+                .collect(),
+        }
+    }
+
+    /// Calls `f` with the coordinates of and a mutable reference to every cell, in parallel.
+    pub fn par_for_each_mut(&mut self, f: impl Fn(usize, usize, &mut T) + Sync) {
+        let width = self.width;
+        self.cells
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, t)| f(i % width, i / width, t));
+    }
+}
+
+/// Serial fallbacks so code written against the parallel API still builds without `rayon`.
+#[cfg(not(feature = "rayon"))]
+impl<T: Sync> Grid<T> {
+    pub fn par_map<U: Send>(&self, f: impl Fn(&T) -> U + Sync + Send) -> Grid<U> {
+        self.map(f)
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+impl<T: Send> Grid<T> {
+    pub fn par_new(width: usize, height: usize, init: impl Fn(usize, usize) -> T + Sync) -> Self {
+        Self {
+            width,
+            height,
+            cells: (0..width * height)
+                .map(|i| init(i % width, i / width))
+                .collect(),
+        }
+    }
+
+    pub fn par_for_each_mut(&mut self, f: impl Fn(usize, usize, &mut T) + Sync) {
+        for (x, y, t) in self.enumerate_mut() {
+            f(x, y, t);
+        }
+    }
 }
 
 impl<T> GridLike<T> for Grid<T> {
@@ -154,81 +299,133 @@ impl<T, const W: usize, const H: usize> GridLike<T> for ArrayGrid<T, W, H> {
     }
 }
 
+/// How a window's coordinates relate to the cell it was opened at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// The cell is at `(0, 0)` in the middle of the window, so coordinates run from `-size / 2`
+    /// to `size / 2`. The size must be odd.
+    Center,
+    /// The cell is at `(0, 0)` in the top left corner of the window, so coordinates run from `0`
+    /// to `size - 1`.
+    TopLeft,
+}
+
+/// What a window sees where it hangs over the edge of its grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdgePolicy<T> {
+    /// Nothing; cells past the edge are missing from the window.
+    Skip,
+    /// The nearest cell on the edge.
+    Clamp,
+    /// The cell on the opposite side of the grid.
+    Wrap,
+    /// The given value.
+    Constant(T),
+}
+
 pub struct GridWindows<'a, T, G: GridLike<T>> {
     grid: &'a G,
     enumerator: GridEnumerator,
     size: usize,
-    _p: PhantomData<T>,
-}
-
-impl<'a, T, G: GridLike<T>> GridWindows<'a, T, G> {
-    fn new(grid: &'a G, size: usize) -> Self {
-        Self {
-            grid,
-            enumerator: GridEnumerator::new(grid),
-            size,
-            _p: PhantomData,
-        }
-    }
+    anchor: Anchor,
+    edges: EdgePolicy<T>,
 }
 
-impl<'a, T, G: GridLike<T>> Iterator for GridWindows<'a, T, G> {
+impl<'a, T: Clone, G: GridLike<T>> Iterator for GridWindows<'a, T, G> {
     type Item = GridWindow<'a, T, G>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.enumerator
             .next()
-            .map(|xy| self.grid.window_at(self.size, xy))
+            .map(|xy| GridWindow::new(self.grid, xy, self.size, self.anchor, self.edges.clone()))
     }
 }
 
+/// A square view onto part of a grid. Window coordinates are relative to the cell the window was
+/// opened at, with the range set by its [`Anchor`]; [`GridWindow::get`], [`GridWindow::iter`] and
+/// [`GridWindow::enumerate`] all use the same coordinates.
 #[derive(Debug, Clone)]
 pub struct GridWindow<'a, T, G: GridLike<T>> {
     grid: &'a G,
     x: usize,
     y: usize,
     size: usize,
-    _p: PhantomData<T>,
+    anchor: Anchor,
+    edges: EdgePolicy<T>,
 }
 
-impl<'a, T, G: GridLike<T>> GridLike<T> for GridWindow<'a, T, G> {
-    fn get(&self, x: isize, y: isize) -> Option<&T> {
-        self.grid.get(self.x as isize + x, self.y as isize + y)
+impl<'a, T, G: GridLike<T>> GridWindow<'a, T, G> {
+    pub fn new(
+        grid: &'a G,
+        (x, y): (usize, usize),
+        size: usize,
+        anchor: Anchor,
+        edges: EdgePolicy<T>,
+    ) -> Self {
+        assert!(
+            anchor != Anchor::Center || size % 2 == 1,
+            "Centered windows should have an odd size"
+        );
+        Self {
+            grid,
+            x,
+            y,
+            size,
+            anchor,
+            edges,
+        }
     }
 
-    fn height(&self) -> usize {
+    pub fn size(&self) -> usize {
         self.size
     }
 
-    fn width(&self) -> usize {
-        self.size
+    /// The smallest coordinate in the window, along either axis.
+    pub fn min(&self) -> isize {
+        match self.anchor {
+            Anchor::Center => -(self.size as isize / 2),
+            Anchor::TopLeft => 0,
+        }
     }
-}
 
-impl<'a, T, G: GridLike<T>> GridWindow<'a, T, G> {
+    /// The largest coordinate in the window, along either axis.
+    pub fn max(&self) -> isize {
+        self.min() + self.size as isize - 1
+    }
+
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        (self.min()..=self.max()).contains(&x) && (self.min()..=self.max()).contains(&y)
+    }
+
+    /// The cell at window coordinates `(x, y)`, or `None` if they lie outside the window or the
+    /// edge policy skips them.
+    pub fn get(&self, x: isize, y: isize) -> Option<&T> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        let (gx, gy) = (self.x as isize + x, self.y as isize + y);
+        let (w, h) = (self.grid.width() as isize, self.grid.height() as isize);
+        if (0..w).contains(&gx) && (0..h).contains(&gy) {
+            return self.grid.get(gx, gy);
+        }
+        match &self.edges {
+            EdgePolicy::Skip => None,
+            EdgePolicy::Clamp => self.grid.get(gx.clamp(0, w - 1), gy.clamp(0, h - 1)),
+            EdgePolicy::Wrap => self.grid.get(gx.rem_euclid(w), gy.rem_euclid(h)),
+            EdgePolicy::Constant(t) => Some(t),
+        }
+    }
+
+    /// Every cell in the window, in row-major order.
     pub fn iter(&self) -> impl Iterator<Item = &T> {
-        (0..self.height())
-            .flat_map(move |y| {
-                (0..self.width()).map(move |x| {
-                    (
-                        x as isize - (self.width() / 2) as isize,
-                        y as isize - (self.height() / 2) as isize,
-                    )
-                })
-            })
-            .filter_map(|(x, y)| self.get(x, y))
+        self.enumerate().map(|(_, _, t)| t)
     }
 
+    /// Every cell in the window with its window coordinates, in row-major order.
     pub fn enumerate(&self) -> impl Iterator<Item = (isize, isize, &T)> {
-        (0..self.height())
-            .flat_map(move |y| {
-                (0..self.width()).map(move |x| {
-                    (
-                        x as isize - (self.width() / 2) as isize,
-                        y as isize - (self.height() / 2) as isize,
-                    )
-                })
-            })
+        let (min, max) = (self.min(), self.max());
+        (min..=max)
+            .flat_map(move |y| (min..=max).map(move |x| (x, y)))
             .filter_map(|(x, y)| self.get(x, y).map(|t| (x, y, t)))
     }
 }
@@ -244,13 +441,20 @@ pub struct GridEnumerator {
 
 impl GridEnumerator {
     pub fn new<T, G: GridLike<T>>(grid: &G) -> Self {
+        Self::with_size(grid.width(), grid.height())
+    }
+
+    /// Enumerates the cells of a `width` x `height` grid without needing the grid itself.
+    pub fn with_size(width: usize, height: usize) -> Self {
+        // A grid with no columns has no cells, however many rows it has.
+        let height = if width == 0 { 0 } else { height };
         Self {
-            width: grid.width(),
-            height: grid.height(),
+            width,
+            height,
             x: 0,
             y: 0,
-            rx: (grid.width() - 1) as isize,
-            ry: (grid.height() - 1) as isize,
+            rx: width as isize - 1,
+            ry: height as isize - 1,
         }
     }
 }
@@ -286,3 +490,317 @@ impl DoubleEndedIterator for GridEnumerator {
         Some(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3 grid whose cells hold `x + 10 * y`.
+    fn grid() -> Grid<i32> {
+        Grid::new(3, 3, |x, y| x as i32 + 10 * y as i32)
+    }
+
+    fn cells<T: Clone, G: GridLike<T>>(window: &GridWindow<'_, T, G>) -> Vec<T> {
+        window.iter().cloned().collect()
+    }
+
+    #[test]
+    fn centered_window_is_relative_to_its_cell() {
+        let grid = grid();
+        let w = grid.window_at((1, 1), 3, Anchor::Center, EdgePolicy::Skip);
+        assert_eq!((w.min(), w.max()), (-1, 1));
+        assert_eq!(w.get(0, 0), Some(&11));
+        assert_eq!(w.get(-1, -1), Some(&0));
+        assert_eq!(w.get(1, 1), Some(&22));
+        assert_eq!(w.get(1, -1), Some(&2));
+    }
+
+    #[test]
+    fn top_left_window_starts_at_its_cell() {
+        let grid = grid();
+        let w = grid.window_at((1, 1), 2, Anchor::TopLeft, EdgePolicy::Skip);
+        assert_eq!((w.min(), w.max()), (0, 1));
+        assert_eq!(w.get(0, 0), Some(&11));
+        assert_eq!(w.get(1, 1), Some(&22));
+        assert_eq!(cells(&w), vec![11, 12, 21, 22]);
+    }
+
+    #[test]
+    fn get_outside_window_is_none() {
+        let grid = Grid::new(9, 9, |x, y| x + y);
+        let centered = grid.window_at((4, 4), 3, Anchor::Center, EdgePolicy::Clamp);
+        assert_eq!(centered.get(2, 0), None);
+        assert_eq!(centered.get(0, -2), None);
+        let top_left = grid.window_at((4, 4), 3, Anchor::TopLeft, EdgePolicy::Wrap);
+        assert_eq!(top_left.get(-1, 0), None);
+        assert_eq!(top_left.get(0, 3), None);
+    }
+
+    #[test]
+    fn get_agrees_with_enumerate() {
+        let grid = grid();
+        for anchor in [Anchor::Center, Anchor::TopLeft] {
+            for edges in [
+                EdgePolicy::Skip,
+                EdgePolicy::Clamp,
+                EdgePolicy::Wrap,
+                EdgePolicy::Constant(-1),
+            ] {
+                for w in grid.windows(3, anchor, edges) {
+                    for (x, y, t) in w.enumerate() {
+                        assert_eq!(w.get(x, y), Some(t));
+                    }
+                    let expected: Vec<_> = (w.min()..=w.max())
+                        .flat_map(|y| (w.min()..=w.max()).map(move |x| (x, y)))
+                        .filter_map(|(x, y)| w.get(x, y).copied())
+                        .collect();
+                    assert_eq!(cells(&w), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn enumerate_is_row_major_in_window_coordinates() {
+        let grid = grid();
+        let w = grid.window_at((1, 1), 3, Anchor::Center, EdgePolicy::Skip);
+        let coords: Vec<_> = w.enumerate().map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(
+            coords,
+            vec![
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (0, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_drops_cells_past_the_edge() {
+        let grid = grid();
+        let w = grid.window_at((0, 0), 3, Anchor::Center, EdgePolicy::Skip);
+        assert_eq!(w.get(-1, 0), None);
+        assert_eq!(w.get(0, -1), None);
+        assert_eq!(cells(&w), vec![0, 1, 10, 11]);
+    }
+
+    #[test]
+    fn clamp_repeats_the_nearest_edge_cell() {
+        let grid = grid();
+        let w = grid.window_at((0, 0), 3, Anchor::Center, EdgePolicy::Clamp);
+        assert_eq!(cells(&w), vec![0, 0, 1, 0, 0, 1, 10, 10, 11]);
+        let w = grid.window_at((2, 2), 3, Anchor::Center, EdgePolicy::Clamp);
+        assert_eq!(cells(&w), vec![11, 12, 12, 21, 22, 22, 21, 22, 22]);
+    }
+
+    #[test]
+    fn wrap_reads_the_opposite_side() {
+        let grid = grid();
+        let w = grid.window_at((0, 0), 3, Anchor::Center, EdgePolicy::Wrap);
+        assert_eq!(cells(&w), vec![22, 20, 21, 2, 0, 1, 12, 10, 11]);
+        let w = grid.window_at((2, 1), 3, Anchor::Center, EdgePolicy::Wrap);
+        assert_eq!(w.get(1, 0), Some(&10));
+    }
+
+    #[test]
+    fn wrap_can_wrap_more_than_once() {
+        let grid = Grid::new(1, 1, |_, _| 7);
+        let w = grid.window_at((0, 0), 5, Anchor::Center, EdgePolicy::Wrap);
+        assert_eq!(cells(&w), vec![7; 25]);
+    }
+
+    #[test]
+    fn constant_fills_cells_past_the_edge() {
+        let grid = grid();
+        let w = grid.window_at((2, 0), 3, Anchor::Center, EdgePolicy::Constant(-1));
+        assert_eq!(cells(&w), vec![-1, -1, -1, 1, 2, -1, 11, 12, -1]);
+    }
+
+    #[test]
+    fn every_policy_agrees_inside_the_grid() {
+        let grid = Grid::new(5, 5, |x, y| x + 5 * y);
+        for edges in [
+            EdgePolicy::Skip,
+            EdgePolicy::Clamp,
+            EdgePolicy::Wrap,
+            EdgePolicy::Constant(100),
+        ] {
+            let w = grid.window_at((2, 2), 3, Anchor::Center, edges);
+            assert_eq!(cells(&w), vec![6, 7, 8, 11, 12, 13, 16, 17, 18]);
+        }
+    }
+
+    #[test]
+    fn windows_visits_every_cell_in_row_major_order() {
+        let grid = grid();
+        let centers: Vec<_> = grid
+            .windows(3, Anchor::Center, EdgePolicy::Skip)
+            .map(|w| *w.get(0, 0).unwrap())
+            .collect();
+        assert_eq!(centers, vec![0, 1, 2, 10, 11, 12, 20, 21, 22]);
+    }
+
+    #[test]
+    fn windows_keep_their_edge_policy() {
+        let grid = grid();
+        let counts: Vec<_> = grid
+            .windows(3, Anchor::Center, EdgePolicy::Constant(0))
+            .map(|w| w.iter().count())
+            .collect();
+        assert_eq!(counts, vec![9; 9]);
+    }
+
+    #[test]
+    fn size_one_window_is_just_the_cell() {
+        let grid = grid();
+        for anchor in [Anchor::Center, Anchor::TopLeft] {
+            let w = grid.window_at((2, 1), 1, anchor, EdgePolicy::Wrap);
+            assert_eq!(cells(&w), vec![12]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "odd size")]
+    fn centered_window_rejects_even_size() {
+        let grid = grid();
+        grid.window_at((1, 1), 2, Anchor::Center, EdgePolicy::Skip);
+    }
+
+    fn rows<T: Clone>(grid: &Grid<T>) -> Vec<Vec<T>> {
+        (0..grid.height())
+            .map(|y| {
+                (0..grid.width())
+                    .map(|x| grid.get(x as isize, y as isize).unwrap().clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resize_keeps_cells_in_place() {
+        let mut g = grid();
+        g.resize(4, 2, -1);
+        assert_eq!(rows(&g), vec![vec![0, 1, 2, -1], vec![10, 11, 12, -1]]);
+        g.resize(1, 3, -2);
+        assert_eq!(rows(&g), vec![vec![0], vec![10], vec![-2]]);
+    }
+
+    #[test]
+    fn crop_is_cut_off_at_the_edge() {
+        let g = grid();
+        assert_eq!(rows(&g.crop(1, 0, 2, 2)), vec![vec![1, 2], vec![11, 12]]);
+        assert_eq!(rows(&g.crop(2, 1, 5, 5)), vec![vec![12], vec![22]]);
+        assert_eq!(g.crop(3, 0, 1, 1).width(), 0);
+    }
+
+    #[test]
+    fn blit_copies_only_what_overlaps() {
+        let mut g = grid();
+        let stamp = Grid::new(2, 2, |x, y| -1 - x as i32 - 2 * y as i32);
+        g.blit(&stamp, -1, 2);
+        assert_eq!(
+            rows(&g),
+            vec![vec![0, 1, 2], vec![10, 11, 12], vec![-2, 21, 22]]
+        );
+        g.blit(&stamp, 1, 0);
+        assert_eq!(
+            rows(&g),
+            vec![vec![0, -1, -2], vec![10, -3, -4], vec![-2, 21, 22]]
+        );
+    }
+
+    #[test]
+    fn draw_rect_is_cut_off_at_the_edge() {
+        let mut g = Grid::new(3, 3, |_, _| 0);
+        g.draw_rect(-1, 1, 3, 5, 1);
+        assert_eq!(rows(&g), vec![vec![0, 0, 0], vec![1, 1, 0], vec![1, 1, 0]]);
+    }
+
+    #[test]
+    fn draw_line_includes_both_ends() {
+        let mut g = Grid::new(5, 3, |_, _| 0);
+        g.draw_line((0, 0), (4, 2), 1);
+        assert_eq!(
+            rows(&g),
+            vec![
+                vec![1, 0, 0, 0, 0],
+                vec![0, 1, 1, 0, 0],
+                vec![0, 0, 0, 1, 1]
+            ]
+        );
+
+        let mut g = Grid::new(3, 3, |_, _| 0);
+        g.draw_line((2, 2), (2, 2), 1);
+        g.draw_line((1, 2), (-3, -2), 2);
+        assert_eq!(rows(&g), vec![vec![0, 0, 0], vec![2, 0, 0], vec![0, 2, 1]]);
+    }
+
+    #[test]
+    fn draw_circle_sets_cells_within_the_radius() {
+        let mut g = Grid::new(5, 5, |_, _| 0);
+        g.draw_circle(2, 2, 1.0, 1);
+        assert_eq!(
+            rows(&g),
+            vec![
+                vec![0, 0, 0, 0, 0],
+                vec![0, 0, 1, 0, 0],
+                vec![0, 1, 1, 1, 0],
+                vec![0, 0, 1, 0, 0],
+                vec![0, 0, 0, 0, 0],
+            ]
+        );
+
+        let mut g = Grid::new(3, 3, |_, _| 0);
+        g.draw_circle(0, 0, 1.5, 1);
+        assert_eq!(rows(&g), vec![vec![1, 1, 0], vec![1, 1, 0], vec![0, 0, 0]]);
+    }
+
+    #[test]
+    fn flood_fill_stops_at_other_values_and_corners() {
+        let mut g = Grid::from_cells(
+            4,
+            3,
+            vec![
+                0, 0, 1, 0, //
+                1, 0, 1, 0, //
+                0, 1, 0, 0, //
+            ],
+        );
+        assert_eq!(g.flood_fill(0, 0, 2), 3);
+        assert_eq!(
+            rows(&g),
+            vec![vec![2, 2, 1, 0], vec![1, 2, 1, 0], vec![0, 1, 0, 0]]
+        );
+        assert_eq!(g.flood_fill(3, 0, 3), 4);
+        assert_eq!(
+            rows(&g),
+            vec![vec![2, 2, 1, 3], vec![1, 2, 1, 3], vec![0, 1, 3, 3]]
+        );
+        assert_eq!(g.flood_fill(0, 0, 2), 0);
+        assert_eq!(g.flood_fill(-1, 0, 5), 0);
+    }
+
+    #[test]
+    fn deserializing_checks_the_size() {
+        let grid: Grid<i32> =
+            serde_json::from_str(r#"{"width": 2, "height": 1, "cells": [3, 4]}"#).unwrap();
+        assert_eq!(grid.iter().copied().collect::<Vec<_>>(), vec![3, 4]);
+        assert!(
+            serde_json::from_str::<Grid<i32>>(r#"{"width": 2, "height": 2, "cells": [3, 4]}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn window_works_on_any_grid_like() {
+        let grid = ArrayGrid::<i32, 3, 3>::new(|x, y| x as i32 + 10 * y as i32);
+        let w = grid.window_at((2, 2), 3, Anchor::Center, EdgePolicy::Skip);
+        assert_eq!(cells(&w), vec![11, 12, 21, 22]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Grid, GridLike};
+
+/// A grid divided into square chunks that fall asleep once they stop changing, so updates can
+/// skip the parts of a large world that have settled.
+///
+/// Cells are stored in one row-major [`Grid`], which the chunked grid dereferences to, so code
+/// that only reads cells does not need to know about chunks. A chunk is *active*, and should be
+/// updated, while it or any chunk around it is awake. Chunks wake when one of their cells is
+/// changed through [`ChunkedGrid::get_mut`] or [`ChunkedGrid::wake`], and are put back to sleep
+/// by [`ChunkedGrid::settle`] once an update leaves them unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ChunkedCells<T>")]
+pub struct ChunkedGrid<T> {
+    cells: Grid<T>,
+    chunk_size: usize,
+    awake: Grid<bool>,
+    active: Grid<bool>,
+}
+
+/// A chunked grid as it is read from a file, before its chunks have been checked against its
+/// cells.
+#[derive(Deserialize)]
+struct ChunkedCells<T> {
+    cells: Grid<T>,
+    chunk_size: usize,
+    awake: Grid<bool>,
+    active: Grid<bool>,
+}
+
+impl<T> TryFrom<ChunkedCells<T>> for ChunkedGrid<T> {
+    type Error = String;
+
+    fn try_from(grid: ChunkedCells<T>) -> Result<Self, Self::Error> {
+        if grid.chunk_size == 0 {
+            return Err("Chunks should not be empty".to_string());
+        }
+        let chunks = (
+            grid.cells.width().div_ceil(grid.chunk_size),
+            grid.cells.height().div_ceil(grid.chunk_size),
+        );
+        for flags in [&grid.awake, &grid.active] {
+            if (flags.width(), flags.height()) != chunks {
+                return Err(format!(
+                    "A {}x{} grid in chunks of {} should have {}x{} chunk flags, not {}x{}",
+                    grid.cells.width(),
+                    grid.cells.height(),
+                    grid.chunk_size,
+                    chunks.0,
+                    chunks.1,
+                    flags.width(),
+                    flags.height()
+                ));
+            }
+        }
+        Ok(Self {
+            cells: grid.cells,
+            chunk_size: grid.chunk_size,
+            awake: grid.awake,
+            active: grid.active,
+        })
+    }
+}
+
+impl<T> ChunkedGrid<T> {
+    /// Divides `cells` into `chunk_size` x `chunk_size` chunks, all of them awake. Chunks along
+    /// the right and bottom edges are cut short when the size doesn't divide evenly.
+    pub fn new(cells: Grid<T>, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Chunks should not be empty");
+        let mut chunked = Self {
+            cells,
+            chunk_size,
+            awake: Grid::new(0, 0, |_, _| true),
+            active: Grid::new(0, 0, |_, _| true),
+        };
+        chunked.wake_all();
+        chunked
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// One flag per chunk, set while the chunk is awake.
+    pub fn awake_chunks(&self) -> &Grid<bool> {
+        &self.awake
+    }
+
+    /// The chunk containing the cell at `(x, y)`.
+    pub fn chunk_of(&self, x: usize, y: usize) -> (usize, usize) {
+        (x / self.chunk_size, y / self.chunk_size)
+    }
+
+    /// Whether the cell at `(x, y)` should be updated, that is, whether its chunk or any chunk
+    /// next to it is awake. Chunks wrap around the edges of the grid, so a change along one edge
+    /// also keeps the chunks along the opposite edge active.
+    pub fn is_active(&self, x: isize, y: isize) -> bool {
+        if x < 0 || y < 0 {
+            return false;
+        }
+        let (cx, cy) = self.chunk_of(x as usize, y as usize);
+        self.active
+            .get(cx as isize, cy as isize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Wakes the chunk containing the cell at `(x, y)`.
+    pub fn wake(&mut self, x: usize, y: usize) {
+        let (cx, cy) = self.chunk_of(x, y);
+        let Some(awake) = self.awake.get_mut(cx as isize, cy as isize) else {
+            return;
+        };
+        if !*awake {
+            *awake = true;
+            self.refresh_active();
+        }
+    }
+
+    /// Wakes every chunk. This also lays the chunks out afresh, so call it after resizing the
+    /// cells through [`ChunkedGrid::cells_mut`].
+    pub fn wake_all(&mut self) {
+        let (chunks_wide, chunks_high) = (
+            self.cells.width().div_ceil(self.chunk_size),
+            self.cells.height().div_ceil(self.chunk_size),
+        );
+        self.awake = Grid::new(chunks_wide, chunks_high, |_, _| true);
+        self.active = Grid::new(chunks_wide, chunks_high, |_, _| true);
+    }
+
+    /// Gets a cell for writing, waking its chunk.
+    pub fn get_mut(&mut self, x: isize, y: isize) -> Option<&mut T> {
+        if self.cells.get(x, y).is_some() {
+            self.wake(x as usize, y as usize);
+        }
+        self.cells.get_mut(x, y)
+    }
+
+    /// Gets every cell for writing without waking anything. Meant for updates that end with
+    /// [`ChunkedGrid::settle`]; anything else should wake the chunks it changes.
+    pub fn cells_mut(&mut self) -> &mut Grid<T> {
+        &mut self.cells
+    }
+
+    /// Puts every chunk whose cells are all `unchanged` from `before` to sleep, and wakes every
+    /// other chunk.
+    pub fn settle(&mut self, before: &Grid<T>, unchanged: impl Fn(&T, &T) -> bool) {
+        assert_eq!(
+            (self.cells.width(), self.cells.height()),
+            (before.width(), before.height()),
+            "Settled grid should be the same size as before"
+        );
+        self.awake.fill(false);
+        for ((x, y, new), old) in self.cells.enumerate().zip(before.iter()) {
+            if !unchanged(old, new) {
+                let (cx, cy) = (x / self.chunk_size, y / self.chunk_size);
+                *self.awake.get_mut(cx as isize, cy as isize).unwrap() = true;
+            }
+        }
+        self.refresh_active();
+    }
+
+    fn refresh_active(&mut self) {
+        let awake = &self.awake;
+        let (w, h) = (awake.width() as isize, awake.height() as isize);
+        self.active = Grid::new(awake.width(), awake.height(), |cx, cy| {
+            (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .any(|(dx, dy)| {
+                    *awake
+                        .get(
+                            (cx as isize + dx).rem_euclid(w),
+                            (cy as isize + dy).rem_euclid(h),
+                        )
+                        .unwrap()
+                })
+        });
+    }
+}
+
+impl<T> Deref for ChunkedGrid<T> {
+    type Target = Grid<T>;
+
+    fn deref(&self) -> &Grid<T> {
+        &self.cells
+    }
+}
+
+impl<T> GridLike<T> for ChunkedGrid<T> {
+    fn get(&self, x: isize, y: isize) -> Option<&T> {
+        self.cells.get(x, y)
+    }
+
+    fn height(&self) -> usize {
+        self.cells.height()
+    }
+
+    fn width(&self) -> usize {
+        self.cells.width()
+    }
+}
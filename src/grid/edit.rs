@@ -0,0 +1,119 @@
+use super::{Grid, GridLike};
+
+/// Operations for building worlds by hand. Anything that would reach past the edge of the grid is
+/// cut off rather than treated as an error.
+impl<T: Clone> Grid<T> {
+    /// Resizes the grid to `width` x `height`, keeping every cell at the same coordinates and
+    /// filling new cells with `fill`.
+    pub fn resize(&mut self, width: usize, height: usize, fill: T) {
+        let cells = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                self.get(x as isize, y as isize)
+                    .cloned()
+                    .unwrap_or_else(|| fill.clone())
+            })
+            .collect();
+        *self = Grid::from_cells(width, height, cells);
+    }
+
+    /// The `width` x `height` rectangle with its top left corner at `(x, y)`.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Grid<T> {
+        let width = width.min(self.width.saturating_sub(x));
+        let height = height.min(self.height.saturating_sub(y));
+        let cells = (y..y + height)
+            .flat_map(|cy| (x..x + width).map(move |cx| (cx, cy)))
+            .map(|(cx, cy)| self.get(cx as isize, cy as isize).unwrap().clone())
+            .collect();
+        Grid::from_cells(width, height, cells)
+    }
+
+    /// Copies `other` into this grid with its top left corner at `(x, y)`.
+    pub fn blit(&mut self, other: &Grid<T>, x: isize, y: isize) {
+        for (ox, oy, t) in other.enumerate() {
+            if let Some(cell) = self.get_mut(x + ox as isize, y + oy as isize) {
+                *cell = t.clone();
+            }
+        }
+    }
+
+    /// Sets every cell in the `width` x `height` rectangle with its top left corner at `(x, y)`.
+    pub fn draw_rect(&mut self, x: isize, y: isize, width: usize, height: usize, value: T) {
+        for cy in y..y + height as isize {
+            for cx in x..x + width as isize {
+                if let Some(cell) = self.get_mut(cx, cy) {
+                    *cell = value.clone();
+                }
+            }
+        }
+    }
+
+    /// Sets every cell whose center is within `radius` cells of the center of the cell at
+    /// `(x, y)`.
+    pub fn draw_circle(&mut self, x: isize, y: isize, radius: f32, value: T) {
+        let r = radius.floor() as isize;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if ((dx * dx + dy * dy) as f32).sqrt() > radius {
+                    continue;
+                }
+                if let Some(cell) = self.get_mut(x + dx, y + dy) {
+                    *cell = value.clone();
+                }
+            }
+        }
+    }
+
+    /// Sets every cell on the line from `from` to `to`, including both ends. Consecutive cells
+    /// on the line share an edge or a corner.
+    pub fn draw_line(&mut self, from: (isize, isize), to: (isize, isize), value: T) {
+        let ((mut x, mut y), (x1, y1)) = (from, to);
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+        let mut error = dx + dy;
+        loop {
+            if let Some(cell) = self.get_mut(x, y) {
+                *cell = value.clone();
+            }
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Grid<T> {
+    /// Sets the cell at `(x, y)`, and every cell connected to it through edge-sharing cells equal
+    /// to it, to `value`. Returns how many cells changed.
+    pub fn flood_fill(&mut self, x: isize, y: isize, value: T) -> usize {
+        let Some(target) = self.get(x, y).cloned() else {
+            return 0;
+        };
+        if target == value {
+            return 0;
+        }
+
+        let mut filled = 0;
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            match self.get_mut(x, y) {
+                Some(cell) if *cell == target => {
+                    *cell = value.clone();
+                    filled += 1;
+                    stack.extend([(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]);
+                }
+                _ => {}
+            }
+        }
+        filled
+    }
+}
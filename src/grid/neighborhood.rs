@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// The cells around a center cell that a stencil reads, described by their offsets from the
+/// center.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// Cells within Manhattan distance `radius`. A radius of 1 is the four cells sharing an edge
+    /// with the center.
+    VonNeumann { radius: usize },
+    /// Cells within Chebyshev distance `radius`. A radius of 1 is the surrounding 3x3 block.
+    Moore { radius: usize },
+    /// Cells within `radius_x` columns and `radius_y` rows of the center.
+    Rect { radius_x: usize, radius_y: usize },
+    /// Exactly the given offsets. Stencils only conserve what they exchange between neighbors if
+    /// the opposite of every offset is also in the mask.
+    Mask(Vec<(isize, isize)>),
+}
+
+impl Default for Neighborhood {
+    fn default() -> Self {
+        Neighborhood::Moore { radius: 1 }
+    }
+}
+
+impl Neighborhood {
+    /// The offset of every neighbor, excluding the center, in row-major order and without
+    /// duplicates.
+    pub fn offsets(&self) -> Vec<(isize, isize)> {
+        let mut offsets: Vec<_> = match self {
+            Neighborhood::VonNeumann { radius } => {
+                let r = *radius as isize;
+                rect(*radius, *radius)
+                    .filter(|(dx, dy)| dx.abs() + dy.abs() <= r)
+                    .collect()
+            }
+            Neighborhood::Moore { radius } => rect(*radius, *radius).collect(),
+            Neighborhood::Rect { radius_x, radius_y } => rect(*radius_x, *radius_y).collect(),
+            Neighborhood::Mask(offsets) => offsets.clone(),
+        };
+        offsets.retain(|&offset| offset != (0, 0));
+        offsets.sort_unstable_by_key(|&(dx, dy)| (dy, dx));
+        offsets.dedup();
+        offsets
+    }
+
+    /// The offset of every neighbor on a hex lattice, in axial coordinates: `q` steps east and `r`
+    /// steps south-east. Von Neumann and Moore neighborhoods are both every cell within `radius`
+    /// steps, rectangles span `radius_x` steps along `q` and `radius_y` along `r`, and masks are
+    /// read as axial offsets.
+    pub fn axial_offsets(&self) -> Vec<(isize, isize)> {
+        let mut offsets: Vec<_> = match self {
+            Neighborhood::VonNeumann { radius } | Neighborhood::Moore { radius } => {
+                let r = *radius as isize;
+                rect(*radius, *radius)
+                    .filter(|(dq, dr)| dq.abs() + dr.abs() + (dq + dr).abs() <= 2 * r)
+                    .collect()
+            }
+            Neighborhood::Rect { .. } | Neighborhood::Mask(_) => self.offsets(),
+        };
+        offsets.retain(|&offset| offset != (0, 0));
+        offsets.sort_unstable_by_key(|&(dq, dr)| (dr, dq));
+        offsets.dedup();
+        offsets
+    }
+
+    /// Whether the opposite of every offset is also a neighbor, so that exchanges between
+    /// neighbors come in equal and opposite pairs.
+    pub fn is_symmetric(&self) -> bool {
+        let offsets = self.offsets();
+        offsets.iter().all(|&(dx, dy)| {
+            offsets
+                .binary_search_by_key(&(-dy, -dx), |&(x, y)| (y, x))
+                .is_ok()
+        })
+    }
+}
+
+fn rect(radius_x: usize, radius_y: usize) -> impl Iterator<Item = (isize, isize)> {
+    let (rx, ry) = (radius_x as isize, radius_y as isize);
+    (-ry..=ry).flat_map(move |dy| (-rx..=rx).map(move |dx| (dx, dy)))
+}
+
+crate::impl_not_evolved!(Neighborhood);
@@ -0,0 +1,98 @@
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use super::neighborhood::Neighborhood;
+
+/// How the cells of a grid are arranged in the plane.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Topology {
+    /// Cells on a square lattice, with neighbors given by offsets in grid coordinates.
+    #[default]
+    Square,
+    /// Cells on a lattice of pointy-topped hexagons, stored with every odd row shifted half a
+    /// cell to the right. Every cell is the same distance from each of its six nearest neighbors,
+    /// and neighborhoods are read in axial coordinates. Wrapping across the top and bottom edges
+    /// only lines up when the height is even.
+    Hex,
+}
+
+impl Topology {
+    /// The center of the cell at `(x, y)`, in units of the distance between nearest neighbors.
+    pub fn position(&self, x: isize, y: isize) -> Vector2<f32> {
+        match self {
+            Topology::Square => Vector2::new(x as f32, y as f32),
+            Topology::Hex => Vector2::new(
+                x as f32 + 0.5 * y.rem_euclid(2) as f32,
+                y as f32 * hex_row_height(),
+            ),
+        }
+    }
+
+    /// Where the cell `(dx, dy)` away from a cell in row `y` lies relative to it.
+    pub fn displacement(&self, dx: isize, dy: isize, y: isize) -> Vector2<f32> {
+        self.position(dx, y + dy) - self.position(0, y)
+    }
+
+    /// The offsets to the cells in `neighborhood` on this lattice.
+    pub fn stencil(&self, neighborhood: &Neighborhood) -> Stencil {
+        let rows = [0, 1].map(|y| {
+            let offsets: Vec<_> = match self {
+                Topology::Square => neighborhood.offsets(),
+                Topology::Hex => neighborhood
+                    .axial_offsets()
+                    .into_iter()
+                    .map(|(dq, dr)| (dq + (y + dr).div_euclid(2) - y.div_euclid(2), dr))
+                    .collect(),
+            };
+            std::iter::once((0, 0))
+                .chain(offsets)
+                .map(|(dx, dy)| Offset {
+                    dx,
+                    dy,
+                    displacement: self.displacement(dx, dy, y),
+                })
+                .collect()
+        });
+        Stencil { rows }
+    }
+}
+
+/// The height of a row of hexagons, in units of the distance between their centers.
+fn hex_row_height() -> f32 {
+    3.0f32.sqrt() / 2.0
+}
+
+crate::impl_not_evolved!(Topology);
+
+/// A neighbor's place relative to a cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Offset {
+    pub dx: isize,
+    pub dy: isize,
+    /// Where the neighbor's center lies relative to the cell's center.
+    pub displacement: Vector2<f32>,
+}
+
+/// The neighbors of every cell under some topology and neighborhood. On a hex lattice the offsets
+/// in grid coordinates differ between even and odd rows, so both are kept.
+#[derive(Debug, Clone)]
+pub struct Stencil {
+    rows: [Vec<Offset>; 2],
+}
+
+impl Stencil {
+    /// The neighbors of a cell in row `y`, not including the cell itself.
+    pub fn offsets(&self, y: isize) -> &[Offset] {
+        &self.with_center(y)[1..]
+    }
+
+    /// The cell itself, at offset zero, followed by its neighbors.
+    pub fn with_center(&self, y: isize) -> &[Offset] {
+        &self.rows[y.rem_euclid(2) as usize]
+    }
+
+    /// How many neighbors each cell has.
+    pub fn neighbor_count(&self) -> usize {
+        self.rows[0].len() - 1
+    }
+}
@@ -0,0 +1,147 @@
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::GridEnumerator;
+
+/// The order in which order-sensitive passes visit the cells of a grid. Whichever cell is visited
+/// first wins ties, so a fixed order gives the world a preferred direction.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Traversal {
+    /// Left to right along each row, top row first.
+    RowMajor,
+    /// Left to right along even rows and right to left along odd rows, top row first.
+    Serpentine,
+    /// Every cell with an even `x + y` in row-major order, then every cell with an odd `x + y`.
+    Checkerboard,
+    /// A fresh random permutation of the cells on every tick.
+    Random,
+    /// Row-major on even ticks, and row-major backwards, from the bottom right, on odd ticks.
+    #[default]
+    Alternating,
+}
+
+impl Traversal {
+    /// The coordinates of every cell of a `width` x `height` grid, in the order to visit them on
+    /// update number `tick`. Only [`Traversal::Random`] draws from `rng`.
+    pub fn order<R: Rng>(
+        &self,
+        width: usize,
+        height: usize,
+        tick: u64,
+        rng: &mut R,
+    ) -> Vec<(usize, usize)> {
+        let row_major = GridEnumerator::with_size(width, height);
+        match self {
+            Traversal::RowMajor => row_major.collect(),
+            Traversal::Serpentine => row_major
+                .map(|(x, y)| {
+                    if y % 2 == 0 {
+                        (x, y)
+                    } else {
+                        (width - 1 - x, y)
+                    }
+                })
+                .collect(),
+            Traversal::Checkerboard => {
+                let (even, odd): (Vec<_>, Vec<_>) = row_major.partition(|(x, y)| (x + y) % 2 == 0);
+                even.into_iter().chain(odd).collect()
+            }
+            Traversal::Random => {
+                let mut order: Vec<_> = row_major.collect();
+                order.shuffle(rng);
+                order
+            }
+            Traversal::Alternating if tick.is_multiple_of(2) => row_major.collect(),
+            Traversal::Alternating => row_major.rev().collect(),
+        }
+    }
+}
+
+crate::impl_not_evolved!(Traversal);
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const ALL: [Traversal; 5] = [
+        Traversal::RowMajor,
+        Traversal::Serpentine,
+        Traversal::Checkerboard,
+        Traversal::Random,
+        Traversal::Alternating,
+    ];
+
+    fn order(traversal: Traversal, width: usize, height: usize, tick: u64) -> Vec<(usize, usize)> {
+        traversal.order(width, height, tick, &mut ChaCha8Rng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn every_order_visits_every_cell_once() {
+        let sizes = [
+            (0, 0),
+            (0, 3),
+            (3, 0),
+            (1, 1),
+            (1, 4),
+            (4, 1),
+            (3, 2),
+            (5, 4),
+        ];
+        for traversal in ALL {
+            for (width, height) in sizes {
+                for tick in 0..2 {
+                    let mut cells = order(traversal, width, height, tick);
+                    cells.sort_unstable_by_key(|&(x, y)| (y, x));
+                    let row_major: Vec<_> = GridEnumerator::with_size(width, height).collect();
+                    assert_eq!(cells, row_major, "{traversal:?} {width}x{height}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn serpentine_turns_back_on_every_other_row() {
+        assert_eq!(
+            order(Traversal::Serpentine, 3, 3, 0),
+            [
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (2, 1),
+                (1, 1),
+                (0, 1),
+                (0, 2),
+                (1, 2),
+                (2, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn checkerboard_visits_even_cells_first() {
+        assert_eq!(
+            order(Traversal::Checkerboard, 3, 2, 0),
+            [(0, 0), (2, 0), (1, 1), (1, 0), (0, 1), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn alternating_reverses_on_odd_ticks() {
+        let forward = [(0, 0), (1, 0), (0, 1), (1, 1)];
+        let backward = [(1, 1), (0, 1), (1, 0), (0, 0)];
+        assert_eq!(order(Traversal::Alternating, 2, 2, 0), forward);
+        assert_eq!(order(Traversal::Alternating, 2, 2, 1), backward);
+        assert_eq!(order(Traversal::Alternating, 2, 2, 4), forward);
+        assert_eq!(order(Traversal::Alternating, 2, 2, 7), backward);
+    }
+
+    #[test]
+    fn random_orders_repeat_with_the_same_seed() {
+        let random = |seed| Traversal::Random.order(5, 4, 0, &mut ChaCha8Rng::seed_from_u64(seed));
+        assert_eq!(random(1), random(1));
+        assert_ne!(random(1), random(2));
+    }
+}
@@ -3,3 +3,30 @@ pub mod grid;
 pub mod pageflip;
 pub mod polynomail;
 pub mod simulation;
+
+/// Implements [`genetic::Gen`], [`genetic::Crossover`] and [`genetic::Mutate`] for config types
+/// that are part of a [`Config`](simulation::config::Config) without being part of what it
+/// evolves: the scenario a world is set up in, or the algorithms and numerics used to simulate it.
+/// Generating gives the default, crossover keeps the first parent's value and mutation does
+/// nothing, so whatever was chosen by hand survives evolution unchanged.
+macro_rules! impl_not_evolved {
+    ($($t:ty),+ $(,)?) => {$(
+        impl ::genetic::Gen for $t {
+            fn gen<R: ::rand::Rng>(_rng: &mut R) -> Self {
+                Self::default()
+            }
+        }
+
+        impl ::genetic::Crossover for $t {
+            fn crossover<R: ::rand::Rng>(&self, _other: &Self, _rng: &mut R) -> Self {
+                self.clone()
+            }
+        }
+
+        impl ::genetic::Mutate for $t {
+            fn mutate<R: ::rand::Rng>(&mut self, _rate: f32, _rng: &mut R) {}
+        }
+    )+};
+}
+
+pub(crate) use impl_not_evolved;
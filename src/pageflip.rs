@@ -1,4 +1,6 @@
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageFlip<T> {
     p1: T,
     p2: T,
@@ -33,6 +35,15 @@ impl<T> PageFlip<T> {
         }
     }
 
+    /// Both pages, the one being read first.
+    pub fn pages(&self) -> [&T; 2] {
+        if self.flipped {
+            [&self.p2, &self.p1]
+        } else {
+            [&self.p1, &self.p2]
+        }
+    }
+
     pub fn flip(&mut self) {
         self.flipped = !self.flipped;
     }
@@ -1,52 +1,191 @@
+pub mod arbiter;
+pub mod boundary;
 pub mod config;
 pub mod conflict;
+pub mod error;
 pub mod forcefield;
+pub mod observer;
+
+use std::{
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use enum_ordinalize::Ordinalize;
 use image::{GenericImage, Pixel, Rgb, RgbImage};
 use ordered_float::OrderedFloat;
 use palette::{convert::IntoColorUnclamped, FromColor, IntoColor, LinSrgb, Mix, Srgb};
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::grid::{Grid, GridEnumerator, GridLike};
+use crate::grid::{
+    chunked::ChunkedGrid,
+    topology::{Stencil, Topology},
+    Grid, GridLike,
+};
 
 use self::{
-    config::Config,
-    conflict::{reduce_potential_moves, PotentialMoves},
+    arbiter::Contest,
+    boundary::{Boundaries, Boundary, Edge, Neighbor},
+    config::{Config, DiffusionMode, ElementDefinition},
+    conflict::{alignment, reduce_potential_moves, MovementStats, PotentialMoves, Source},
+    error::SimulationError,
     forcefield::ForceField,
+    observer::{Observers, Phase, SimulationEvent, SimulationObserver},
 };
 
-#[derive(Debug, Clone)]
+/// The side length of the chunks the world is divided into for sleeping.
+const CHUNK_SIZE: usize = 32;
+
+/// A complete world. Serializing a state captures everything an update depends on, including the
+/// force buffers and the random number generator, so a state loaded from a snapshot continues
+/// exactly as the original would have. Observers are not saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "StateFields")]
 pub struct State {
-    pub elements: Grid<Tile>,
+    pub elements: ChunkedGrid<Tile>,
     pub config: Config,
     potential_moves: Grid<PotentialMoves>,
     forces: ForceField,
     pub conflict_iters: usize,
+    /// How many updates have run, which some traversal orders use to vary from tick to tick.
+    tick: u64,
+    rng: ChaCha8Rng,
+    conservation_tolerance: Option<f64>,
+    sleep_tolerance: Option<f32>,
+    record_movement_stats: bool,
+    #[serde(skip)]
+    movement_stats: Option<MovementStats>,
+    #[serde(skip)]
+    observers: Observers,
+}
+
+/// A state as it is read from a snapshot, before its parts have been checked against each other.
+#[derive(Deserialize)]
+struct StateFields {
+    elements: ChunkedGrid<Tile>,
+    config: Config,
+    potential_moves: Grid<PotentialMoves>,
+    forces: ForceField,
+    conflict_iters: usize,
+    #[serde(default)]
+    tick: u64,
+    rng: ChaCha8Rng,
+    conservation_tolerance: Option<f64>,
+    sleep_tolerance: Option<f32>,
+    #[serde(default)]
+    record_movement_stats: bool,
+}
+
+impl TryFrom<StateFields> for State {
+    type Error = String;
+
+    fn try_from(state: StateFields) -> Result<Self, Self::Error> {
+        let (width, height) = (state.elements.width(), state.elements.height());
+        let moves = (
+            state.potential_moves.width(),
+            state.potential_moves.height(),
+        );
+        if moves != (width, height) {
+            return Err(format!(
+                "A {width}x{height} world should not have {}x{} potential moves",
+                moves.0, moves.1
+            ));
+        }
+        if !state.forces.has_size(width, height) {
+            return Err(format!(
+                "A {width}x{height} world should have forces of the same size"
+            ));
+        }
+        let defined = state.config.elements.len();
+        if let Some(t) = state.elements.iter().find(|t| t.element.index() >= defined) {
+            return Err(format!(
+                "Tile of element {} when only {defined} elements are defined",
+                t.element.index()
+            ));
+        }
+        Ok(Self {
+            elements: state.elements,
+            config: state.config,
+            potential_moves: state.potential_moves,
+            forces: state.forces,
+            conflict_iters: state.conflict_iters,
+            tick: state.tick,
+            rng: state.rng,
+            conservation_tolerance: state.conservation_tolerance,
+            sleep_tolerance: state.sleep_tolerance,
+            record_movement_stats: state.record_movement_stats,
+            movement_stats: None,
+            observers: Observers::default(),
+        })
+    }
 }
 
 impl State {
     pub fn gen(config: Config, width: usize, height: usize) -> Self {
-        let mut _self = Self {
-            elements: Grid::new(width, height, |_, _| {
-                let mut rng = rand::thread_rng();
-                let element = unsafe {
-                    Element::from_ordinal_unsafe(rng.gen_range(0..Element::variant_count() as i8))
-                };
+        Self::gen_with_rng(config, width, height, &mut rand::thread_rng())
+    }
+
+    /// Generates a world that is fully determined by `seed`: the same config, size and seed
+    /// always produce the same tiles and the same sequence of updates.
+    pub fn gen_with_seed(config: Config, width: usize, height: usize, seed: u64) -> Self {
+        Self::gen_with_rng(config, width, height, &mut ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    /// Generates a world from `rng`. The state keeps its own generator, seeded from `rng`, for
+    /// any randomness needed by later updates.
+    pub fn gen_with_rng<R: Rng>(config: Config, width: usize, height: usize, rng: &mut R) -> Self {
+        let spawnable: Vec<_> = config
+            .elements
+            .iter()
+            .filter_map(|(element, d)| d.spawn.as_ref().map(|spawn| (element, spawn)))
+            .collect();
+        let cells = (0..width * height)
+            .map(|_| {
+                let (element, spawn) = spawnable
+                    .choose(&mut *rng)
+                    .expect("Config should have at least one spawnable element");
                 Tile {
-                    saturation: match element {
-                        Element::Air => rng.gen_range(0.5..=0.75),
-                        Element::Soil => rng.gen_range(0.5..=0.9),
-                        Element::Water => 1.0,
-                    }
-                    .into(),
-                    element,
+                    saturation: rng
+                        .gen_range(spawn.min_saturation.as_f32()..=spawn.max_saturation.as_f32())
+                        .into(),
+                    temperature: rng
+                        .gen_range(spawn.min_temperature.as_f32()..=spawn.max_temperature.as_f32())
+                        .into(),
+                    element: *element,
                 }
-            }),
+            })
+            .collect();
+
+        Self::new(
+            config,
+            Grid::from_cells(width, height, cells),
+            ChaCha8Rng::from_rng(rng).expect("Seeding from another rng should not fail"),
+        )
+    }
+
+    /// Builds a world from hand-made tiles. Updates are fully determined by the tiles, config and
+    /// `seed`.
+    pub fn from_tiles(config: Config, tiles: Grid<Tile>, seed: u64) -> Self {
+        Self::new(config, tiles, ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    fn new(config: Config, tiles: Grid<Tile>, rng: ChaCha8Rng) -> Self {
+        let (width, height) = (tiles.width(), tiles.height());
+        let mut _self = Self {
+            elements: ChunkedGrid::new(tiles, CHUNK_SIZE),
             config,
             potential_moves: Grid::new(width, height, |_, _| PotentialMoves::new(vec![])),
             forces: ForceField::new(width, height),
             conflict_iters: 0,
+            tick: 0,
+            rng,
+            conservation_tolerance: None,
+            sleep_tolerance: None,
+            record_movement_stats: false,
+            movement_stats: None,
+            observers: Observers::default(),
         };
 
         _self.forces.init(&_self.config, &_self.elements);
@@ -54,6 +193,47 @@ impl State {
         _self
     }
 
+    /// Writes a snapshot of the state as pretty-printed JSON, for debugging. JSON has no way to
+    /// write NaN or infinite numbers, which come out as `null`, so a snapshot of a world whose
+    /// forces have diverged saves but fails to load; use [`State::save_binary`] for those.
+    pub fn save_json(&self, writer: impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+
+    /// Reads a snapshot written by [`State::save_json`], failing if its parts don't fit together.
+    pub fn load_json(reader: impl Read) -> serde_json::Result<Self> {
+        serde_json::from_reader(reader)
+    }
+
+    /// Writes a snapshot of the state in a compact binary format, for long runs.
+    pub fn save_binary(&self, writer: impl Write) -> bincode::Result<()> {
+        bincode::serialize_into(writer, self)
+    }
+
+    /// Reads a snapshot written by [`State::save_binary`], failing if its parts don't fit
+    /// together.
+    pub fn load_binary(reader: impl Read) -> bincode::Result<Self> {
+        bincode::deserialize_from(reader)
+    }
+
+    /// Edits the tiles by hand, for example with [`Grid::draw_rect`] or [`Grid::blit`], then
+    /// brings everything derived from them up to date: every chunk wakes, and forces are
+    /// recomputed from scratch. The edit may resize the world.
+    pub fn edit<R>(&mut self, edit: impl FnOnce(&mut Grid<Tile>) -> R) -> R {
+        let result = edit(self.elements.cells_mut());
+
+        let (width, height) = (self.elements.width(), self.elements.height());
+        if (width, height) != (self.potential_moves.width(), self.potential_moves.height()) {
+            self.potential_moves = Grid::new(width, height, |_, _| PotentialMoves::new(vec![]));
+            self.forces = ForceField::new(width, height);
+            self.movement_stats = None;
+        }
+        self.elements.wake_all();
+        self.forces.init(&self.config, &self.elements);
+
+        result
+    }
+
     pub fn to_image(&self) -> RgbImage {
         let f = self.forces.force_image();
         let pr = self.forces.pressure_image();
@@ -77,7 +257,34 @@ impl State {
             *p = Rgb([color.red, color.green, color.blue])
         }
 
-        img
+        lay_out(self.config.topology, img)
+    }
+
+    /// How the tiles moved during the last update, or `None` unless
+    /// [`set_movement_stats`](Self::set_movement_stats) was enabled before it.
+    pub fn movement_stats(&self) -> Option<&MovementStats> {
+        self.movement_stats.as_ref()
+    }
+
+    /// A heatmap of how contested every cell was during the last update: black where at most one
+    /// tile claimed the cell, brightening through red to yellow toward the most contested cell.
+    /// All black unless [`set_movement_stats`](Self::set_movement_stats) is enabled.
+    pub fn conflict_image(&self) -> RgbImage {
+        let (width, height) = (self.elements.width() as u32, self.elements.height() as u32);
+        let mut img = RgbImage::new(width, height);
+        let Some(stats) = &self.movement_stats else {
+            return lay_out(self.config.topology, img);
+        };
+
+        let max_claimants = stats.claimants.iter().copied().max().unwrap_or(0).max(2);
+        for (x, y, p) in img.enumerate_pixels_mut() {
+            let claimants = *stats.claimants.get(x as isize, y as isize).unwrap();
+            let heat = claimants.saturating_sub(1) as f32 / (max_claimants - 1) as f32;
+            let p_color = Srgb::new(heat.sqrt(), heat * heat, 0.0).into_format::<u8>();
+            *p = Rgb([p_color.red, p_color.green, p_color.blue]);
+        }
+
+        lay_out(self.config.topology, img)
     }
 
     fn element_image(&self) -> RgbImage {
@@ -88,138 +295,509 @@ impl State {
                 .elements
                 .get(x as isize, y as isize)
                 .expect("Image made from grid should have same size");
-            let p_color = t.color();
+            let p_color = t.color(&self.config);
             *p = Rgb([p_color.red, p_color.green, p_color.blue])
         }
 
         img
     }
 
-    pub fn update(&mut self) {
-        let start = std::time::Instant::now();
-        self.update_position();
-        self.update_saturations();
-        self.update_elements();
-        let elapsed = std::time::Instant::now() - start;
-        // println!("Update: {}s", elapsed.as_secs_f32());
+    /// Advances the world by one tick. If this fails, the tick is left half done and the state
+    /// should be discarded.
+    pub fn update(&mut self) -> Result<(), SimulationError> {
+        let start = Instant::now();
+        let moisture_before = self.conservation_tolerance.map(|_| self.total_moisture());
+        let elements_before = self.sleep_tolerance.map(|_| (*self.elements).clone());
+        self.timed(Phase::Forces, Self::update_forces);
+        let mut exchanged_moisture = self.timed(Phase::Movement, Self::update_position)?;
+        exchanged_moisture += self.timed(Phase::Inflow, Self::update_inflow);
+        self.timed(Phase::Saturation, Self::update_saturations);
+        self.timed(Phase::Absorption, Self::update_absorption);
+        self.timed(Phase::Temperature, Self::update_temperatures);
+        exchanged_moisture += self.timed(Phase::Elements, Self::update_elements);
+        if let (Some(before), Some(tolerance)) = (moisture_before, self.conservation_tolerance) {
+            let after = self.total_moisture();
+            debug_assert!(
+                (after - before - exchanged_moisture).abs() <= tolerance * before.max(1.0),
+                "Moisture not conserved: {before} before update, {after} after, \
+                 {exchanged_moisture} exchanged through boundaries and transitions"
+            );
+        }
+        if let (Some(before), Some(tolerance)) = (elements_before, self.sleep_tolerance) {
+            self.elements
+                .settle(&before, |old, new| new.is_near(old, tolerance));
+        }
+        self.tick += 1;
+        self.observers.emit(SimulationEvent::PhaseTiming {
+            phase: Phase::Update,
+            elapsed: start.elapsed(),
+        });
+        Ok(())
     }
 
-    fn update_position(&mut self) {
-        // self.forces.update(&self.elements, &self.config, &mut rng);
+    /// Registers an observer to receive events from every later update. Keep a clone of the
+    /// `Arc` to read back whatever the observer collects.
+    pub fn add_observer(&mut self, observer: Arc<Mutex<dyn SimulationObserver>>) {
+        self.observers.add(observer);
+    }
 
-        self.forces.init(&self.config, &self.elements);
-        for _ in 0..10 {
-            self.forces.update(&self.elements, &self.config);
+    fn timed<T>(&mut self, phase: Phase, update: impl FnOnce(&mut Self) -> T) -> T {
+        let start = Instant::now();
+        let result = update(self);
+        self.observers.emit(SimulationEvent::PhaseTiming {
+            phase,
+            elapsed: start.elapsed(),
+        });
+        result
+    }
+
+    /// Enables a debug assertion, checked after every [`update`](Self::update), that total
+    /// moisture changes by no more than `tolerance` (relative to the total) apart from what
+    /// enters or leaves through boundaries and the saturation explicitly set by transitions.
+    /// Only meaningful with [`DiffusionMode::Conservative`], since relaxation does not conserve
+    /// moisture.
+    pub fn set_conservation_check(&mut self, tolerance: Option<f64>) {
+        self.conservation_tolerance = tolerance;
+    }
+
+    /// Lets chunks of the world sleep, skipping them in later updates, once an update changes
+    /// none of their tiles' saturation or temperature by more than `tolerance` and moves none of
+    /// their tiles. Sleeping chunks wake when a neighboring chunk changes. Sleeping chunks get no
+    /// new forces and no tile moves into them, so this trades accuracy for speed. Defaults to
+    /// `None`, which keeps the whole world awake; a tolerance of zero only lets chunks that have
+    /// settled exactly fall asleep.
+    pub fn set_sleep_tolerance(&mut self, tolerance: Option<f32>) {
+        self.sleep_tolerance = tolerance;
+        if tolerance.is_none() {
+            self.elements.wake_all();
         }
-        self.potential_moves = self.forces.potential_moves();
+    }
 
-        let (moves, conflict_iters) =
-            reduce_potential_moves(&self.forces, &mut self.potential_moves);
-        self.conflict_iters = conflict_iters;
-        self.elements = Grid::new(self.elements.width(), self.elements.height(), |x, y| {
-            let (old_x, old_y) = moves.get(x as isize, y as isize).unwrap();
-            self.elements.get(*old_x, *old_y).unwrap().clone()
-        });
+    /// Gathers [`MovementStats`] during every later update, for
+    /// [`movement_stats`](Self::movement_stats) and [`conflict_image`](Self::conflict_image).
+    /// Defaults to off, since they take time to gather.
+    pub fn set_movement_stats(&mut self, enabled: bool) {
+        self.record_movement_stats = enabled;
+        if !enabled {
+            self.movement_stats = None;
+        }
     }
 
-    fn update_saturations(&mut self) {
-        let saturations = self
+    pub fn total_moisture(&self) -> f64 {
+        self.elements.iter().map(|t| t.saturation.0 as f64).sum()
+    }
+
+    /// Total moisture held by each element, in registry order.
+    pub fn moisture_by_element(&self) -> Vec<(Element, f64)> {
+        let mut totals: Vec<_> = self
+            .config
             .elements
-            .windows(3)
-            .map(|w| {
-                let total: f32 = w.iter().map(|t| t.saturation.0).sum();
-                let count = w.iter().count();
-                let avg = total / count as f32;
-                let target = avg;
-                let diff = target - w.get(0, 0).unwrap().saturation.0;
-                self.config.saturation_diffusion_rate.as_f32() * diff
+            .iter()
+            .map(|(element, _)| (element, 0.0))
+            .collect();
+        for t in self.elements.iter() {
+            totals[t.element.index()].1 += t.saturation.0 as f64;
+        }
+        totals
+    }
+
+    fn update_forces(&mut self) {
+        self.forces.init(&self.config, &self.elements);
+        for iteration in 0..10 {
+            let residual = self.forces.update(&self.elements, &self.config);
+            self.observers
+                .emit(SimulationEvent::ForceRelaxation { iteration, residual });
+        }
+
+        if !self.observers.is_empty() {
+            let (min, max, mean) = self.forces.pressure_stats();
+            self.observers
+                .emit(SimulationEvent::Pressure { min, max, mean });
+        }
+    }
+
+    /// Moves tiles, returning the net moisture added by tiles leaving through open boundaries
+    /// and the tiles that replace them.
+    fn update_position(&mut self) -> Result<f64, SimulationError> {
+        self.potential_moves = self.forces.potential_moves(
+            &self.elements,
+            &self.config.boundaries,
+            &self.config.movement,
+            &self.config.stencil(),
+        );
+
+        let order = self.config.traversal.order(
+            self.elements.width(),
+            self.elements.height(),
+            self.tick,
+            &mut self.rng,
+        );
+        let contest = Contest {
+            forces: &self.forces,
+            elements: &self.elements,
+            config: &self.config,
+        };
+        let resolution = reduce_potential_moves(
+            self.config.resolver,
+            &self.config.arbiter,
+            &contest,
+            &mut self.potential_moves,
+            &order,
+            &mut self.rng,
+            self.record_movement_stats,
+        )?;
+        let moves = resolution.sources;
+        self.conflict_iters = resolution.iterations;
+        if !self.observers.is_empty() {
+            self.observers.emit(SimulationEvent::ConflictResolution {
+                iterations: resolution.iterations,
+                bids: resolution.bids,
+                alignment: alignment(&contest, &moves),
+            });
+        }
+
+        let (width, height) = (self.elements.width(), self.elements.height());
+        let mut moisture = 0.0;
+        let cells = moves
+            .iter()
+            .map(|source| match *source {
+                Source::Tile(old_x, old_y)
+                | Source::Path {
+                    tile: (old_x, old_y),
+                    ..
+                } => self.elements.get(old_x, old_y).unwrap().clone(),
+                Source::Exited {
+                    tile: (ox, oy),
+                    target: (tx, ty),
+                } => {
+                    let Neighbor::Outside(edge) =
+                        self.config.boundaries.resolve(tx, ty, width, height)
+                    else {
+                        unreachable!("Exits should target cells outside the grid");
+                    };
+                    let Boundary::Open(fill) = self.config.boundaries.get(edge) else {
+                        unreachable!("Tiles should only exit through open boundaries");
+                    };
+                    let fill = fill.to_tile(&self.config.elements);
+                    let exited = self.elements.get(ox, oy).unwrap();
+                    moisture += (fill.saturation.0 - exited.saturation.0) as f64;
+                    fill
+                }
             })
             .collect();
-        let saturations =
-            Grid::from_cells(self.elements.width(), self.elements.height(), saturations);
+        *self.elements.cells_mut() = Grid::from_cells(width, height, cells);
+
+        if !self.observers.is_empty() {
+            let count = moves
+                .enumerate()
+                .filter(|(x, y, source)| **source != Source::Tile(*x as isize, *y as isize))
+                .count();
+            self.observers.emit(SimulationEvent::TilesMoved { count });
+        }
+        self.movement_stats = resolution.stats;
+
+        Ok(moisture)
+    }
+
+    /// Replaces tiles along inflow boundaries with the tiles they emit, returning the net
+    /// moisture added.
+    fn update_inflow(&mut self) -> f64 {
+        let (width, height) = (self.elements.width(), self.elements.height());
+        let mut moisture = 0.0;
+        for edge in Edge::ALL {
+            let Boundary::Inflow { tile, rate } = self.config.boundaries.get(edge) else {
+                continue;
+            };
+            let tile = tile.to_tile(&self.config.elements);
+            for (x, y) in edge.cells(width, height) {
+                if self.rng.gen::<f32>() < rate.as_f32() {
+                    let t = self.elements.get_mut(x as isize, y as isize).unwrap();
+                    moisture += (tile.saturation.0 - t.saturation.0) as f64;
+                    *t = tile.clone();
+                }
+            }
+        }
+        moisture
+    }
+
+    fn update_saturations(&mut self) {
+        match self.config.diffusion {
+            DiffusionMode::Relaxation => self.relax_saturations(),
+            DiffusionMode::Conservative => self.diffuse_saturations(),
+        }
+    }
+
+    fn relax_saturations(&mut self) {
+        let saturations = map_surroundings(&self.elements, &self.config, |w| {
+            let total: f32 = w.iter().map(|t| t.saturation.0).sum();
+            let count = w.iter().count();
+            let avg = total / count as f32;
+            let target = avg;
+            let diff = target - w.center().saturation.0;
+            self.config.saturation_diffusion_rate.as_f32() * diff
+        });
 
-        for (x, y) in GridEnumerator::new(&self.elements) {
-            let t = self.elements.get_mut(x as isize, y as isize).unwrap();
+        self.elements.cells_mut().par_for_each_mut(|x, y, t| {
             let s = &mut t.saturation;
             s.0 = (s.0 + saturations.get(x as isize, y as isize).unwrap()).clamp(0.0, 1.0);
+        });
+    }
+
+    /// Exchanges the rate, shared evenly across the neighborhood, times the saturation difference
+    /// between every pair of neighbors. Each tile ends up at a weighted average of itself and its
+    /// neighbors, so saturations stay in [0, 1] without clamping, and total moisture is conserved
+    /// as long as the neighborhood is symmetric.
+    fn diffuse_saturations(&mut self) {
+        let rate = self.config.saturation_diffusion_rate.as_f32() * neighbor_share(&self.config);
+        let saturations = map_surroundings(&self.elements, &self.config, |w| {
+            let s = w.center().saturation.0;
+            w.iter().map(|o| rate * (o.saturation.0 - s)).sum::<f32>()
+        });
+        add_saturations(self.elements.cells_mut(), &saturations);
+    }
+
+    /// Moves moisture from source tiles into neighboring absorbers until they reach capacity,
+    /// then evens it out between neighboring absorbers of the same element. Every exchange is
+    /// between a pair of neighbors and limited to the rate shared evenly across the neighborhood,
+    /// so moisture is conserved and no tile is drained below zero.
+    fn update_absorption(&mut self) {
+        let absorbers = self.config.elements.absorbers();
+        let share = neighbor_share(&self.config);
+
+        let absorbed_from = |t: &Tile, o: &Tile| match absorbers[t.element.index()] {
+            Some((source, a)) if source == o.element => {
+                let room = (a.capacity.as_f32() - t.saturation.0).max(0.0);
+                a.rate.as_f32() * share * o.saturation.0.min(room)
+            }
+            _ => 0.0,
+        };
+        let absorbed = map_surroundings(&self.elements, &self.config, |w| {
+            let t = w.center();
+            w.iter()
+                .map(|o| absorbed_from(t, o) - absorbed_from(o, t))
+                .sum::<f32>()
+        });
+        add_saturations(self.elements.cells_mut(), &absorbed);
+
+        let wicked = map_surroundings(&self.elements, &self.config, |w| {
+            let t = w.center();
+            match absorbers[t.element.index()] {
+                Some((_, a)) => w
+                    .iter()
+                    .filter(|o| o.element == t.element)
+                    .map(|o| a.wicking_rate.as_f32() * share * (o.saturation.0 - t.saturation.0))
+                    .sum::<f32>(),
+                None => 0.0,
+            }
+        });
+        add_saturations(self.elements.cells_mut(), &wicked);
+    }
+
+    /// Exchanges heat between each pair of neighboring tiles in proportion to their temperature
+    /// difference. The exchange is symmetric, so total heat is conserved.
+    fn update_temperatures(&mut self) {
+        let config = &self.config;
+        let share = neighbor_share(config);
+        let temperatures = map_surroundings(&self.elements, config, |w| {
+            let t = w.center();
+            let conductivity = t.conductivity(config);
+            let heat: f32 = w
+                .iter()
+                .map(|o| {
+                    (conductivity * o.conductivity(config)).sqrt()
+                        * (o.temperature.0 - t.temperature.0)
+                })
+                .sum();
+            config.thermal_diffusion_rate.as_f32() * share * heat / t.heat_capacity(config)
+        });
+
+        self.elements.cells_mut().par_for_each_mut(|x, y, t| {
+            t.temperature.0 += temperatures.get(x as isize, y as isize).unwrap();
+        });
+    }
+
+    /// Applies element transitions, returning the net moisture added by transitions that reset
+    /// saturation.
+    fn update_elements(&mut self) -> f64 {
+        let (elements, config) = (&self.elements, &self.config);
+        let transitions = Grid::par_new(elements.width(), elements.height(), |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            if !elements.is_active(x, y) {
+                return None;
+            }
+            config
+                .elements
+                .transition_for(elements.get(x, y).unwrap())
+                .map(|(element, transition)| (element, transition.saturation))
+        });
+
+        let mut moisture = 0.0;
+        let cells = self.elements.cells_mut().enumerate_mut();
+        for ((x, y, t), transition) in cells.zip(transitions.iter()) {
+            let Some((element, saturation)) = *transition else {
+                continue;
+            };
+            self.observers.emit(SimulationEvent::ElementTransition {
+                x,
+                y,
+                from: t.element,
+                to: element,
+            });
+            t.element = element;
+            if let Some(saturation) = saturation {
+                moisture += (saturation.as_f32() - t.saturation.0) as f64;
+                t.saturation = saturation.as_f32().into();
+            }
         }
+        moisture
     }
+}
 
-    fn update_elements(&mut self) {
-        for (x, y) in GridEnumerator::new(&self.elements) {
-            let t = self.elements.get_mut(x as isize, y as isize).unwrap();
-            match t.element {
-                Element::Air
-                    if t.saturation().0
-                        >= self.config.air_to_water_saturation_threshold.as_f32() =>
-                {
-                    t.element = Element::Water
-                }
-                Element::Water
-                    if t.saturation().0
-                        < self.config.water_to_air_saturation_threshold.as_f32() =>
-                {
-                    t.element = Element::Air
-                }
-                _ => {}
+/// A cell and its neighborhood, wrapping across periodic boundaries and cut off at any other edge
+/// and at sleeping chunks.
+#[derive(Clone, Copy)]
+struct Surroundings<'a> {
+    elements: &'a ChunkedGrid<Tile>,
+    boundaries: &'a Boundaries,
+    stencil: &'a Stencil,
+    x: isize,
+    y: isize,
+}
+
+impl<'a> Surroundings<'a> {
+    fn center(&self) -> &'a Tile {
+        self.elements.get(self.x, self.y).unwrap()
+    }
+
+    /// Every tile in the neighborhood, including the center.
+    fn iter(&self) -> impl Iterator<Item = &'a Tile> + 'a {
+        let Self {
+            elements,
+            boundaries,
+            stencil,
+            x,
+            y,
+        } = *self;
+        stencil.with_center(y).iter().filter_map(move |o| {
+            match boundaries.resolve(x + o.dx, y + o.dy, elements.width(), elements.height()) {
+                Neighbor::Inside(nx, ny) if elements.is_active(nx, ny) => elements.get(nx, ny),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Computes a value from the surroundings of every active tile, in parallel. Tiles in sleeping
+/// chunks get the default value.
+fn map_surroundings<T: Send + Default>(
+    elements: &ChunkedGrid<Tile>,
+    config: &Config,
+    f: impl Fn(Surroundings<'_>) -> T + Sync,
+) -> Grid<T> {
+    let stencil = config.stencil();
+    Grid::par_new(elements.width(), elements.height(), |x, y| {
+        let (x, y) = (x as isize, y as isize);
+        if !elements.is_active(x, y) {
+            return T::default();
+        }
+        f(Surroundings {
+            elements,
+            boundaries: &config.boundaries,
+            stencil: &stencil,
+            x,
+            y,
+        })
+    })
+}
+
+/// Arranges an image with one pixel per cell the way `topology` arranges cells. Hex cells are
+/// drawn two pixels wide with odd rows shifted one pixel right, so rows interlock like the
+/// lattice.
+fn lay_out(topology: Topology, cells: RgbImage) -> RgbImage {
+    match topology {
+        Topology::Square => cells,
+        Topology::Hex => {
+            let mut img = RgbImage::new(cells.width() * 2 + 1, cells.height());
+            for (x, y, p) in cells.enumerate_pixels() {
+                let left = 2 * x + y % 2;
+                img.put_pixel(left, y, *p);
+                img.put_pixel(left + 1, y, *p);
             }
+            img
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The part of a rate exchanged with each neighbor, so that a tile exchanges at most the full rate
+/// with its whole neighborhood.
+fn neighbor_share(config: &Config) -> f32 {
+    1.0 / config.stencil().neighbor_count().max(1) as f32
+}
+
+fn add_saturations(elements: &mut Grid<Tile>, deltas: &Grid<f32>) {
+    elements.par_for_each_mut(|x, y, t| {
+        t.saturation.0 += deltas.get(x as isize, y as isize).unwrap();
+    });
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Tile {
     element: Element,
     saturation: OrderedFloat<f32>,
+    temperature: OrderedFloat<f32>,
 }
 
 impl Tile {
-    fn color(&self) -> Srgb<u8> {
-        let air = Srgb::new(221u8, 255, 247)
-            .into_format::<f32>()
-            .into_linear();
-        let water = Srgb::new(46u8, 134, 171).into_format::<f32>().into_linear();
-        let soil = Srgb::new(169u8, 113, 75).into_format::<f32>().into_linear();
-
-        let color = match self.element {
-            Element::Air => air.mix(water, 0.5 * self.saturation.0),
-            Element::Soil => soil * (1.0 - 0.5 * self.saturation.0),
-            Element::Water => water.mix(air, 1.0 - self.saturation.0),
-        };
+    pub fn new(element: Element, saturation: f32, temperature: f32) -> Self {
+        Self {
+            element,
+            saturation: OrderedFloat(saturation),
+            temperature: OrderedFloat(temperature),
+        }
+    }
+
+    /// Whether this tile is the same element as `other`, with saturation and temperature within
+    /// `tolerance` of it.
+    fn is_near(&self, other: &Tile, tolerance: f32) -> bool {
+        self.element == other.element
+            && (self.saturation.0 - other.saturation.0).abs() <= tolerance
+            && (self.temperature.0 - other.temperature.0).abs() <= tolerance
+    }
+
+    fn definition<'a>(&self, config: &'a Config) -> &'a ElementDefinition {
+        config.elements.get(self.element)
+    }
+
+    fn color(&self, config: &Config) -> Srgb<u8> {
+        let definition = self.definition(config);
+        let [r, g, b] = definition.color;
+        let dry = Srgb::new(r, g, b).into_format::<f32>().into_linear();
+        let [r, g, b] = definition.wet_color;
+        let wet = Srgb::new(r, g, b).into_format::<f32>().into_linear();
+
+        let color = dry.mix(wet, self.saturation.0);
 
         color.into()
     }
 
     fn density(&self, config: &Config) -> f32 {
-        match self.element {
-            Element::Air => config.air.density.eval(self.saturation.0),
-            Element::Soil => config.soil.density.eval(self.saturation.0),
-            Element::Water => config.water.density.eval(self.saturation.0),
-        }
+        self.definition(config).config.density.eval(self.saturation.0)
     }
 
     fn cohesion(&self, config: &Config) -> f32 {
-        match self.element {
-            Element::Air => config.air.cohesion.eval(self.saturation.0),
-            Element::Soil => config.soil.cohesion.eval(self.saturation.0),
-            Element::Water => config.water.cohesion.eval(self.saturation.0),
-        }
+        self.definition(config).config.cohesion.eval(self.saturation.0)
     }
 
     fn adhesion(&self, config: &Config) -> f32 {
-        match self.element {
-            Element::Air => config.air.adhesion.eval(self.saturation.0),
-            Element::Soil => config.soil.adhesion.eval(self.saturation.0),
-            Element::Water => config.water.adhesion.eval(self.saturation.0),
-        }
+        self.definition(config).config.adhesion.eval(self.saturation.0)
     }
 
-    fn attractive_force(&self, other: &Self, config: &Config) -> f32 {
-        if self.element == other.element {
-            self.cohesion(config) * other.cohesion(config)
-        } else {
-            self.adhesion(config) * other.adhesion(config)
-        }
+    fn heat_capacity(&self, config: &Config) -> f32 {
+        self.definition(config).config.heat_capacity.as_f32()
+    }
+
+    fn conductivity(&self, config: &Config) -> f32 {
+        self.definition(config).config.conductivity.as_f32()
     }
 
     pub fn element(&self) -> Element {
@@ -229,11 +807,180 @@ impl Tile {
     pub fn saturation(&self) -> OrderedFloat<f32> {
         self.saturation
     }
+
+    pub fn temperature(&self) -> OrderedFloat<f32> {
+        self.temperature
+    }
+}
+
+/// Identifies an element by its position in [`Config::elements`](config::Config::elements).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Element(u8);
+
+impl Element {
+    pub fn from_index(index: usize) -> Self {
+        Self(index.try_into().expect("Element index out of range"))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
 }
 
-#[derive(Debug, Clone, Copy, Ordinalize, PartialEq, Eq, Hash)]
-pub enum Element {
-    Air,
-    Soil,
-    Water,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clamped_f32::ClampedF32,
+        simulation::{
+            boundary::BoundaryTile,
+            config::{PressureMethod, PressureSolver},
+        },
+    };
+
+    /// Configs that between them run every parallel phase.
+    fn configs() -> Vec<Config> {
+        let conservative = Config {
+            diffusion: DiffusionMode::Conservative,
+            ..Config::default()
+        };
+        let multigrid = Config {
+            pressure: PressureSolver {
+                method: PressureMethod::Multigrid,
+                ..PressureSolver::default()
+            },
+            ..Config::default()
+        };
+        vec![Config::default(), conservative, multigrid]
+    }
+
+    /// The tiles of a seeded world after `ticks` updates.
+    fn run(config: Config, ticks: usize) -> Vec<Tile> {
+        let mut state = State::gen_with_seed(config, 24, 16, 7);
+        for _ in 0..ticks {
+            state.update().unwrap();
+        }
+        state.elements.iter().cloned().collect()
+    }
+
+    #[test]
+    fn snapshots_continue_exactly_where_they_left_off() {
+        let mut state = State::gen_with_seed(Config::default(), 24, 16, 7);
+        state.update().unwrap();
+
+        let mut json = vec![];
+        state.save_json(&mut json).unwrap();
+        let mut binary = vec![];
+        state.save_binary(&mut binary).unwrap();
+        let mut loaded = [
+            State::load_json(json.as_slice()).unwrap(),
+            State::load_binary(binary.as_slice()).unwrap(),
+        ];
+
+        state.update().unwrap();
+        for l in loaded.iter_mut() {
+            l.update().unwrap();
+            assert_eq!(
+                bincode::serialize(l).unwrap(),
+                bincode::serialize(&state).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn snapshots_with_mismatched_parts_fail_to_load() {
+        let snapshot = |state: &State| serde_json::to_value(state).unwrap();
+        let load = |json: &serde_json::Value| {
+            State::load_json(json.to_string().as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        let json = snapshot(&State::gen_with_seed(Config::default(), 24, 16, 7));
+        let smaller = snapshot(&State::gen_with_seed(Config::default(), 12, 8, 7));
+
+        for part in ["potential_moves", "forces"] {
+            let mut mismatched = json.clone();
+            mismatched[part] = smaller[part].clone();
+            assert!(load(&mismatched).contains("A 24x16 world should"), "{part}");
+        }
+
+        let mut chunks = json.clone();
+        chunks["elements"]["chunk_size"] = 0.into();
+        assert!(load(&chunks).contains("Chunks should not be empty"));
+        chunks["elements"]["chunk_size"] = 8.into();
+        assert!(load(&chunks).contains("should have 3x2 chunk flags, not 1x1"));
+
+        let mut element = json.clone();
+        element["elements"]["cells"]["cells"][5]["element"] = 200.into();
+        assert!(load(&element).contains("Tile of element 200"));
+    }
+
+    #[test]
+    fn seeded_runs_repeat_exactly() {
+        for config in configs() {
+            assert_eq!(run(config.clone(), 3), run(config, 3));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn updates_do_not_depend_on_the_thread_count() {
+        let on_threads = |threads, config| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| run(config, 3))
+        };
+        for config in configs() {
+            assert_eq!(on_threads(1, config.clone()), on_threads(4, config));
+        }
+    }
+
+    #[test]
+    fn water_pours_in_through_an_inflow_top_and_out_through_an_open_bottom() {
+        let boundary_tile = |element: &str, saturation| BoundaryTile {
+            element: element.to_string(),
+            saturation: ClampedF32::new(saturation),
+            temperature: ClampedF32::new(20.0),
+        };
+        let config = Config {
+            diffusion: DiffusionMode::Conservative,
+            boundaries: Boundaries {
+                top: Boundary::Inflow {
+                    tile: boundary_tile("water", 1.0),
+                    rate: ClampedF32::new(1.0),
+                },
+                bottom: Boundary::Open(boundary_tile("air", 0.5)),
+                ..Boundaries::default()
+            },
+            ..Config::default()
+        };
+        let (air, water) = (
+            config.elements.lookup("air").unwrap(),
+            config.elements.lookup("water").unwrap(),
+        );
+        // Water over air that is drier than the air the open bottom lets in.
+        let tiles = Grid::new(4, 8, |_, y| {
+            if y < 4 {
+                Tile::new(water, 1.0, 20.0)
+            } else {
+                Tile::new(air, 0.2, 20.0)
+            }
+        });
+        let mut state = State::from_tiles(config, tiles, 3);
+        // Checks that the moisture of every tile that leaves and every tile that replaces it is
+        // accounted for.
+        state.set_conservation_check(Some(1e-4));
+
+        for _ in 0..10 {
+            state.update().unwrap();
+            assert!((0..4).all(|x| state.elements.get(x, 0).unwrap().element == water));
+        }
+        assert!(state
+            .elements
+            .iter()
+            .any(|t| t.element == air && t.saturation.0 > 0.4));
+    }
 }
@@ -0,0 +1,388 @@
+use genetic::{Crossover, Gen, Mutate};
+use nalgebra::Vector2;
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::grid::{Grid, GridLike};
+
+use super::{boundary::Boundary, config::Config, ForceField, Tile};
+
+/// Everything an arbiter can look at when choosing between tiles.
+pub struct Contest<'a> {
+    pub forces: &'a ForceField,
+    pub elements: &'a Grid<Tile>,
+    pub config: &'a Config,
+}
+
+impl Contest<'_> {
+    pub fn force(&self, x: isize, y: isize) -> Vector2<f32> {
+        *self.forces.get(x, y).unwrap()
+    }
+
+    pub fn density(&self, x: isize, y: isize) -> f32 {
+        self.elements.get(x, y).unwrap().density(self.config)
+    }
+
+    /// The unit vector from the tile at `from` toward the cell at `to`, taking the short way
+    /// around where that crosses a periodic edge.
+    pub fn direction(&self, from: (isize, isize), to: (isize, isize)) -> Vector2<f32> {
+        let boundaries = &self.config.boundaries;
+        let dx = offset(
+            to.0 - from.0,
+            self.elements.width(),
+            &boundaries.left,
+            &boundaries.right,
+        );
+        let dy = offset(
+            to.1 - from.1,
+            self.elements.height(),
+            &boundaries.top,
+            &boundaries.bottom,
+        );
+        self.config
+            .topology
+            .displacement(dx, dy, from.1)
+            .try_normalize(0.0)
+            .unwrap_or_else(Vector2::zeros)
+    }
+
+    /// Whether the tiles at `a` and `b` should trade places: their forces oppose, with the tile
+    /// at `a` pushed toward `b` and the tile at `b` pushed back toward `a`, and the tile at `a` is
+    /// denser by at least the configured margin.
+    pub fn favors_swap(&self, a: (isize, isize), b: (isize, isize)) -> bool {
+        self.favors_swap_along(a, b, self.direction(a, b))
+    }
+
+    /// Whether the tile at `a`, moving along `direction`, should trade places with the tile at
+    /// `b` on its way, as for [`Contest::favors_swap`].
+    pub fn favors_swap_along(
+        &self,
+        a: (isize, isize),
+        b: (isize, isize),
+        direction: Vector2<f32>,
+    ) -> bool {
+        self.force(a.0, a.1).dot(&direction) > 0.0
+            && self.force(b.0, b.1).dot(&direction) < 0.0
+            && self.density(a.0, a.1) - self.density(b.0, b.1)
+                >= self.config.swap_min_density_difference.as_f32()
+    }
+}
+
+/// The offset `d` along an axis `size` cells long, or the offset the other way around if that
+/// is shorter and crosses an edge that wraps: `low` before the first cell, `high` after the last.
+fn offset(d: isize, size: usize, low: &Boundary, high: &Boundary) -> isize {
+    let size = size as isize;
+    let around = if d > 0 { d - size } else { d + size };
+    let crossed = if around < 0 { low } else { high };
+    if around.abs() < d.abs() && *crossed == Boundary::Periodic {
+        around
+    } else {
+        d
+    }
+}
+
+/// Decides which tile gets a cell that several tiles want to move into.
+pub trait MoveArbiter {
+    /// The index into `candidates`, the coordinates of the tiles trying to move into `target`, of
+    /// the tile that gets it. There are always at least two candidates.
+    fn pick(
+        &self,
+        contest: &Contest,
+        target: (isize, isize),
+        candidates: &[(isize, isize)],
+        rng: &mut dyn RngCore,
+    ) -> usize;
+}
+
+/// The tile pushed hardest, whichever way it is pushed.
+pub struct LargestForce;
+
+impl MoveArbiter for LargestForce {
+    fn pick(
+        &self,
+        contest: &Contest,
+        _target: (isize, isize),
+        candidates: &[(isize, isize)],
+        _rng: &mut dyn RngCore,
+    ) -> usize {
+        index_of_max(candidates, |x, y| contest.force(x, y).norm())
+    }
+}
+
+/// The densest tile, so heavy tiles sink through light ones.
+pub struct HighestDensity;
+
+impl MoveArbiter for HighestDensity {
+    fn pick(
+        &self,
+        contest: &Contest,
+        _target: (isize, isize),
+        candidates: &[(isize, isize)],
+        _rng: &mut dyn RngCore,
+    ) -> usize {
+        index_of_max(candidates, |x, y| contest.density(x, y))
+    }
+}
+
+/// The tile pushed hardest toward the cell, ignoring any push across the move.
+pub struct AlignedForce;
+
+impl MoveArbiter for AlignedForce {
+    fn pick(
+        &self,
+        contest: &Contest,
+        target: (isize, isize),
+        candidates: &[(isize, isize)],
+        _rng: &mut dyn RngCore,
+    ) -> usize {
+        index_of_max(candidates, |x, y| {
+            contest.force(x, y).dot(&contest.direction((x, y), target))
+        })
+    }
+}
+
+/// Any tile, with equal chances.
+pub struct RandomArbiter;
+
+impl MoveArbiter for RandomArbiter {
+    fn pick(
+        &self,
+        _contest: &Contest,
+        _target: (isize, isize),
+        candidates: &[(isize, isize)],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        rng.gen_range(0..candidates.len())
+    }
+}
+
+/// Any tile, with chances in proportion to how hard it is pushed. Tiles that are not pushed at all
+/// only win when no tile is.
+pub struct WeightedRandom;
+
+impl MoveArbiter for WeightedRandom {
+    fn pick(
+        &self,
+        contest: &Contest,
+        target: (isize, isize),
+        candidates: &[(isize, isize)],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        let weights = candidates.iter().map(|&(x, y)| contest.force(x, y).norm());
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => distribution.sample(rng),
+            Err(_) => RandomArbiter.pick(contest, target, candidates, rng),
+        }
+    }
+}
+
+/// The arbiters a [`Config`] can choose between.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Arbiter {
+    #[default]
+    LargestForce,
+    HighestDensity,
+    AlignedForce,
+    Random,
+    WeightedRandom,
+}
+
+impl MoveArbiter for Arbiter {
+    fn pick(
+        &self,
+        contest: &Contest,
+        target: (isize, isize),
+        candidates: &[(isize, isize)],
+        rng: &mut dyn RngCore,
+    ) -> usize {
+        match self {
+            Arbiter::LargestForce => LargestForce.pick(contest, target, candidates, rng),
+            Arbiter::HighestDensity => HighestDensity.pick(contest, target, candidates, rng),
+            Arbiter::AlignedForce => AlignedForce.pick(contest, target, candidates, rng),
+            Arbiter::Random => RandomArbiter.pick(contest, target, candidates, rng),
+            Arbiter::WeightedRandom => WeightedRandom.pick(contest, target, candidates, rng),
+        }
+    }
+}
+
+impl Gen for Arbiter {
+    fn gen<R: Rng>(rng: &mut R) -> Self {
+        *[
+            Arbiter::LargestForce,
+            Arbiter::HighestDensity,
+            Arbiter::AlignedForce,
+            Arbiter::Random,
+            Arbiter::WeightedRandom,
+        ]
+        .choose(rng)
+        .unwrap()
+    }
+}
+
+impl Crossover for Arbiter {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        if rng.gen() {
+            *self
+        } else {
+            *other
+        }
+    }
+}
+
+impl Mutate for Arbiter {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        if rng.gen::<f32>() < rate {
+            *self = Self::gen(rng);
+        }
+    }
+}
+
+/// The index of the candidate with the largest `key`, or of the earliest of them if several tie,
+/// so that ties go to whichever tile was visited first.
+fn index_of_max(candidates: &[(isize, isize)], key: impl Fn(isize, isize) -> f32) -> usize {
+    candidates
+        .iter()
+        .map(|&(x, y)| key(x, y))
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (i, k)| {
+            if k > best.1 {
+                (i, k)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// The tiles of a test world and the forces on them.
+    struct World {
+        config: Config,
+        elements: Grid<Tile>,
+        forces: ForceField,
+    }
+
+    impl World {
+        fn contest(&self) -> Contest<'_> {
+            Contest {
+                forces: &self.forces,
+                elements: &self.elements,
+                config: &self.config,
+            }
+        }
+    }
+
+    /// A 5x5 world of damp soil, pushed by `forces` at the given cells and nowhere else.
+    fn world(forces: &[((isize, isize), Vector2<f32>)]) -> World {
+        let config = Config::default();
+        let soil = config.elements.lookup("soil").unwrap();
+        let elements = Grid::new(5, 5, |_, _| Tile::new(soil, 0.5, 20.0));
+        let mut field = Grid::new(5, 5, |_, _| Vector2::zeros());
+        for &((x, y), force) in forces {
+            *field.get_mut(x, y).unwrap() = force;
+        }
+        World {
+            config,
+            elements,
+            forces: ForceField::with_forces(field),
+        }
+    }
+
+    #[test]
+    fn ties_go_to_the_earliest_candidate() {
+        let mut world = world(&[]);
+        let water = world.config.elements.lookup("water").unwrap();
+        *world.elements.get_mut(1, 1).unwrap() = Tile::new(water, 1.0, 20.0);
+        let contest = world.contest();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let arbiters: [&dyn MoveArbiter; 3] = [&LargestForce, &HighestDensity, &AlignedForce];
+        for arbiter in arbiters {
+            for candidates in [[(0, 0), (2, 0)], [(2, 0), (0, 0)]] {
+                assert_eq!(arbiter.pick(&contest, (1, 0), &candidates, &mut rng), 0);
+            }
+        }
+        // Soil is denser than water, so ties are only broken when nothing else decides.
+        let candidates = [(1, 1), (0, 0), (2, 0)];
+        assert_eq!(
+            HighestDensity.pick(&contest, (1, 0), &candidates, &mut rng),
+            1
+        );
+    }
+
+    #[test]
+    fn directions_only_wrap_across_periodic_edges() {
+        let mut world = world(&[]);
+        let (down, up) = (Vector2::new(0.0, 1.0), Vector2::new(0.0, -1.0));
+        assert_eq!(world.contest().direction((0, 0), (0, 3)), down);
+        assert_eq!(world.contest().direction((0, 4), (0, 1)), up);
+
+        world.config.boundaries.top = Boundary::Periodic;
+        world.config.boundaries.bottom = Boundary::Periodic;
+        assert_eq!(world.contest().direction((0, 0), (0, 3)), up);
+        assert_eq!(world.contest().direction((0, 4), (0, 1)), down);
+        assert_eq!(world.contest().direction((0, 0), (0, 2)), down);
+        // Only the edge the short way crosses has to wrap.
+        world.config.boundaries.bottom = Boundary::Wall;
+        assert_eq!(world.contest().direction((0, 0), (0, 3)), up);
+        assert_eq!(world.contest().direction((0, 4), (0, 1)), up);
+    }
+
+    #[test]
+    fn largest_force_picks_the_tile_pushed_hardest() {
+        let world = world(&[
+            ((1, 2), Vector2::new(1.0, 0.0)),
+            ((2, 1), Vector2::new(0.0, -3.0)),
+        ]);
+        let contest = world.contest();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        assert_eq!(
+            LargestForce.pick(&contest, (2, 2), &[(1, 2), (2, 1)], &mut rng),
+            1
+        );
+        assert_eq!(
+            LargestForce.pick(&contest, (2, 2), &[(2, 1), (1, 2)], &mut rng),
+            0
+        );
+    }
+
+    #[test]
+    fn aligned_force_prefers_a_small_push_toward_the_cell_to_a_large_one_across_it() {
+        let world = world(&[
+            ((1, 2), Vector2::new(1.0, 0.0)),
+            ((2, 1), Vector2::new(4.0, 0.0)),
+        ]);
+        let contest = world.contest();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        assert_eq!(
+            AlignedForce.pick(&contest, (2, 2), &[(2, 1), (1, 2)], &mut rng),
+            1
+        );
+        assert_eq!(
+            LargestForce.pick(&contest, (2, 2), &[(2, 1), (1, 2)], &mut rng),
+            0
+        );
+    }
+
+    #[test]
+    fn weighted_random_never_picks_an_unpushed_tile_over_a_pushed_one() {
+        let world = world(&[((2, 1), Vector2::new(0.0, 0.1))]);
+        let contest = world.contest();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let candidates = [(1, 2), (2, 1), (3, 2)];
+        for _ in 0..100 {
+            assert_eq!(
+                WeightedRandom.pick(&contest, (2, 2), &candidates, &mut rng),
+                1
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+use crate::clamped_f32::ClampedF32;
+
+use super::{
+    config::{ElementRegistry, Temperature},
+    error::ConfigError,
+    Tile,
+};
+
+/// What lies beyond each edge of the world.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub top: Boundary,
+    pub bottom: Boundary,
+}
+
+impl Boundaries {
+    pub fn get(&self, edge: Edge) -> &Boundary {
+        match edge {
+            Edge::Left => &self.left,
+            Edge::Right => &self.right,
+            Edge::Top => &self.top,
+            Edge::Bottom => &self.bottom,
+        }
+    }
+
+    /// Checks that every tile the boundaries emit is of an element in `elements`.
+    pub fn check(&self, elements: &ElementRegistry) -> Result<(), ConfigError> {
+        for edge in Edge::ALL {
+            if let Boundary::Open(tile) | Boundary::Inflow { tile, .. } = self.get(edge) {
+                elements.resolve(&tile.element, || format!("The {edge:?} boundary"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps possibly out-of-bounds coordinates to the cell they refer to, wrapping across
+    /// periodic edges, or to the edge they lie beyond.
+    pub fn resolve(&self, x: isize, y: isize, width: usize, height: usize) -> Neighbor {
+        let (w, h) = (width as isize, height as isize);
+        let x = if x < 0 {
+            if self.left != Boundary::Periodic {
+                return Neighbor::Outside(Edge::Left);
+            }
+            x.rem_euclid(w)
+        } else if x >= w {
+            if self.right != Boundary::Periodic {
+                return Neighbor::Outside(Edge::Right);
+            }
+            x.rem_euclid(w)
+        } else {
+            x
+        };
+        let y = if y < 0 {
+            if self.top != Boundary::Periodic {
+                return Neighbor::Outside(Edge::Top);
+            }
+            y.rem_euclid(h)
+        } else if y >= h {
+            if self.bottom != Boundary::Periodic {
+                return Neighbor::Outside(Edge::Bottom);
+            }
+            y.rem_euclid(h)
+        } else {
+            y
+        };
+        Neighbor::Inside(x, y)
+    }
+}
+
+crate::impl_not_evolved!(Boundaries);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    pub const ALL: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom];
+
+    /// The cells of a `width` x `height` world that lie along this edge.
+    pub fn cells(&self, width: usize, height: usize) -> Vec<(usize, usize)> {
+        match self {
+            Edge::Left => (0..height).map(|y| (0, y)).collect(),
+            Edge::Right => (0..height).map(|y| (width - 1, y)).collect(),
+            Edge::Top => (0..width).map(|x| (x, 0)).collect(),
+            Edge::Bottom => (0..width).map(|x| (x, height - 1)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbor {
+    Inside(isize, isize),
+    Outside(Edge),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Boundary {
+    /// Pushes back on forces and blocks movement.
+    #[default]
+    Wall,
+    /// Wraps around to the opposite edge, which should also be periodic.
+    Periodic,
+    /// Exerts no force and lets tiles leave the world. Each tile that leaves is replaced by the
+    /// given tile, in whichever cell is left empty once the tiles behind it have moved up.
+    Open(BoundaryTile),
+    /// Blocks like a wall, and every update replaces each tile along the edge with the given
+    /// tile with probability `rate`.
+    Inflow {
+        tile: BoundaryTile,
+        rate: ClampedF32<0, 1, 1>,
+    },
+}
+
+impl Boundary {
+    /// Whether the boundary pushes back on forces pointing into it.
+    pub fn is_solid(&self) -> bool {
+        matches!(self, Boundary::Wall | Boundary::Inflow { .. })
+    }
+}
+
+/// A tile that enters the world through a boundary.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BoundaryTile {
+    pub element: String,
+    pub saturation: ClampedF32<0, 1, 1>,
+    pub temperature: Temperature,
+}
+
+impl BoundaryTile {
+    pub fn to_tile(&self, elements: &ElementRegistry) -> Tile {
+        Tile {
+            element: elements
+                .lookup(&self.element)
+                .expect("Boundary elements are checked when the config is built"),
+            saturation: OrderedFloat(self.saturation.as_f32()),
+            temperature: OrderedFloat(self.temperature.as_f32()),
+        }
+    }
+}
@@ -1,55 +1,971 @@
 use genetic::{Crossover, Gen, Mutate};
+use nalgebra::Vector2;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{clamped_f32::ClampedF32, polynomail::Polynomial};
+use crate::{
+    clamped_f32::ClampedF32,
+    grid::{
+        neighborhood::Neighborhood,
+        topology::{Stencil, Topology},
+        traversal::Traversal,
+    },
+    polynomail::Polynomial,
+};
+
+use super::{
+    arbiter::Arbiter, boundary::Boundaries, conflict::Resolver, error::ConfigError, Element, Tile,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
+// The derived (de)serializers are wrapped by the impls below, which check the loaded config.
+#[serde(remote = "Self")]
 pub struct Config {
-    pub air: ElementConfig,
-    pub soil: ElementConfig,
-    pub water: ElementConfig,
-    pub air_to_water_saturation_threshold: ClampedF32<0, 1, 1>,
+    pub elements: ElementRegistry,
+    pub diffusion: DiffusionMode,
     pub saturation_diffusion_rate: ClampedF32<0, 1, 1>,
-    pub water_to_air_saturation_threshold: ClampedF32<0, 1, 1>,
+    pub thermal_diffusion_rate: ClampedF32<0, 1, 1>,
+    pub attraction: Attraction,
+    pub gravity: Gravity,
+    // The fields from here on default when missing.
+    #[serde(default)]
+    pub boundaries: Boundaries,
+    /// The cells around each tile that it pushes on, can move to and exchanges moisture and heat
+    /// with. Should contain the opposite of every offset, so that exchanges conserve what they
+    /// move. Neighborhoods reaching past the adjacent cells let tiles jump over their neighbors.
+    #[serde(default)]
+    pub neighborhood: Neighborhood,
+    #[serde(default)]
+    pub topology: Topology,
+    /// The order in which tiles claim the cells they want to move into. Earlier tiles win ties.
+    #[serde(default)]
+    pub traversal: Traversal,
+    /// How the tiles that want the same cell are sorted out.
+    #[serde(default)]
+    pub resolver: Resolver,
+    /// Who gets a cell when several tiles want to move into it, for the greedy resolver.
+    #[serde(default)]
+    pub arbiter: Arbiter,
+    /// How much denser a tile has to be than its neighbor to trade places with it.
+    #[serde(default = "default_swap_min_density_difference")]
+    pub swap_min_density_difference: ClampedF32<0, 2, 1>,
+    /// How far tiles can travel in one update. Moves longer than one cell are settled before any
+    /// other move, tile by tile in the traversal order, without asking the arbiter: a tile whose
+    /// path crosses the path of a tile visited earlier stops short of it.
+    #[serde(default)]
+    pub movement: Movement,
+    /// How pressure keeps tiles from bunching up or spreading out: a cheap local estimate, or a
+    /// solve for the pressure that makes the forces free of divergence.
+    #[serde(default)]
+    pub pressure: PressureSolver,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            air: ElementConfig {
-                adhesion: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(0.05)]),
-                cohesion: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(0.4)]),
-                density: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(-0.99)]),
-            },
-            soil: ElementConfig {
-                adhesion: Polynomial::new(vec![
-                    ClampedF32::new(0.0),
-                    ClampedF32::new(3.25),
-                    ClampedF32::new(-2.5),
-                ]),
-                cohesion: Polynomial::new(vec![
-                    ClampedF32::new(0.0),
-                    ClampedF32::new(3.25),
-                    ClampedF32::new(-2.5),
-                ]),
-                density: Polynomial::new(vec![ClampedF32::new(1.0), ClampedF32::new(-0.1)]),
-            },
-            water: ElementConfig {
-                adhesion: Polynomial::new(vec![ClampedF32::new(0.75)]),
-                cohesion: Polynomial::new(vec![ClampedF32::new(0.5)]),
-                density: Polynomial::new(vec![ClampedF32::new(0.5), ClampedF32::new(0.1)]),
-            },
-            air_to_water_saturation_threshold: ClampedF32::new(0.9),
+            elements: ElementRegistry::default(),
+            diffusion: DiffusionMode::Relaxation,
             saturation_diffusion_rate: ClampedF32::new(0.01),
-            water_to_air_saturation_threshold: ClampedF32::new(0.5),
+            thermal_diffusion_rate: ClampedF32::new(0.1),
+            attraction: Attraction {
+                radius: ClampedF32::new(1.5),
+                falloff: ClampedF32::new(1.0),
+            },
+            gravity: Gravity {
+                magnitude: ClampedF32::new(10.0),
+                direction: ClampedF32::new(90.0),
+                radial: ClampedF32::new(0.0),
+                center_x: ClampedF32::new(0.5),
+                center_y: ClampedF32::new(0.5),
+                regions: GravityRegions::default(),
+            },
+            boundaries: Boundaries::default(),
+            neighborhood: Neighborhood::default(),
+            topology: Topology::default(),
+            traversal: Traversal::default(),
+            resolver: Resolver::default(),
+            arbiter: Arbiter::default(),
+            swap_min_density_difference: default_swap_min_density_difference(),
+            movement: Movement::default(),
+            pressure: PressureSolver::default(),
         }
     }
 }
 
+fn default_swap_min_density_difference() -> ClampedF32<0, 2, 1> {
+    ClampedF32::new(0.1)
+}
+
+impl Serialize for Config {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Config::serialize(self, serializer)
+    }
+}
+
+/// Rejects configs that fail [`Config::check`], so that a bad config fails to load instead of
+/// failing the first update that needs the missing element.
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = Config::deserialize(deserializer)?;
+        config.check().map_err(de::Error::custom)?;
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Checks that every element the config names outside its registry is defined, and that the
+    /// neighborhood is symmetric. Names inside the registry are checked when it is built, and
+    /// evolution never changes any of these, so only configs put together by hand need checking
+    /// again.
+    pub fn check(&self) -> Result<(), ConfigError> {
+        self.boundaries.check(&self.elements)?;
+        if !self.neighborhood.is_symmetric() {
+            return Err(ConfigError::AsymmetricNeighborhood);
+        }
+        Ok(())
+    }
+
+    /// The neighbors of every tile, laid out on the configured topology.
+    pub fn stencil(&self) -> Stencil {
+        self.topology.stencil(&self.neighborhood)
+    }
+}
+
+/// Cohesion between tiles of the same element and adhesion between tiles of different elements.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
+pub struct Attraction {
+    /// Tiles attract each other when their centers are at most this many cells apart. Below one,
+    /// attraction is disabled.
+    pub radius: ClampedF32<0, 4, 1>,
+    /// Attraction falls off with the distance raised to this power.
+    pub falloff: ClampedF32<0, 3, 1>,
+}
+
+/// How far tiles can travel in one update. Tiles that can't move further than one cell only move
+/// to a neighbor.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
+pub struct Movement {
+    /// Cells traveled per unit of force.
+    pub speed: ClampedF32<0, 1, 1>,
+    /// The most cells a tile can travel, however large its force.
+    pub max_distance: ClampedF32<1, 16, 1>,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            speed: ClampedF32::new(0.25),
+            max_distance: ClampedF32::new(4.0),
+        }
+    }
+}
+
+impl Movement {
+    /// How many cells a tile pushed by `force` can travel.
+    pub fn budget(&self, force: &Vector2<f32>) -> usize {
+        (force.norm() * self.speed.as_f32()).min(self.max_distance.as_f32()) as usize
+    }
+}
+
+/// Gravity, as a blend of a uniform field and a radial field pulling toward a point, with
+/// optional uniform overrides in rectangular regions.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
+pub struct Gravity {
+    pub magnitude: ClampedF32<0, 20, 1>,
+    /// Direction of the uniform field in degrees clockwise from +x, so 90 pulls toward the
+    /// bottom of the world.
+    pub direction: ClampedF32<0, 360, 1>,
+    /// How much of the field points toward the center rather than along `direction`, from 0 for
+    /// fully uniform to 1 for fully radial.
+    pub radial: ClampedF32<0, 1, 1>,
+    /// Horizontal position of the radial center as a fraction of the world width.
+    pub center_x: ClampedF32<0, 1, 1>,
+    /// Vertical position of the radial center as a fraction of the world height.
+    pub center_y: ClampedF32<0, 1, 1>,
+    pub regions: GravityRegions,
+}
+
+/// Regions where gravity is replaced by a uniform field. Later regions take precedence where
+/// they overlap.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct GravityRegions(pub Vec<GravityRegion>);
+
+/// Starts without overrides; regions are only ever added by hand.
+impl Gen for GravityRegions {
+    fn gen<R: Rng>(_rng: &mut R) -> Self {
+        Self::default()
+    }
+}
+
+impl Crossover for GravityRegions {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        if self.0.len() != other.0.len() {
+            return if rng.gen() {
+                self.clone()
+            } else {
+                other.clone()
+            };
+        }
+        Self(
+            self.0
+                .iter()
+                .zip(other.0.iter())
+                .map(|(a, b)| a.crossover(b, rng))
+                .collect(),
+        )
+    }
+}
+
+impl Mutate for GravityRegions {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        for r in self.0.iter_mut() {
+            r.mutate(rate, rng);
+        }
+    }
+}
+
+/// A rectangle, in fractions of the world size, with its own uniform gravity.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
+pub struct GravityRegion {
+    pub x: ClampedF32<0, 1, 1>,
+    pub y: ClampedF32<0, 1, 1>,
+    pub width: ClampedF32<0, 1, 1>,
+    pub height: ClampedF32<0, 1, 1>,
+    pub magnitude: ClampedF32<0, 20, 1>,
+    pub direction: ClampedF32<0, 360, 1>,
+}
+
+impl GravityRegion {
+    pub fn contains(&self, fx: f32, fy: f32) -> bool {
+        fx >= self.x.as_f32()
+            && fx < self.x.as_f32() + self.width.as_f32()
+            && fy >= self.y.as_f32()
+            && fy < self.y.as_f32() + self.height.as_f32()
+    }
+}
+
+/// How saturation spreads between neighboring tiles.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DiffusionMode {
+    /// Pulls each tile toward the average of its neighborhood and clamps to [0, 1]. Moisture is
+    /// created or destroyed by the clamp and at the edges of the world.
+    Relaxation,
+    /// Exchanges moisture symmetrically between neighbors, conserving the total.
+    Conservative,
+}
+
+impl Gen for DiffusionMode {
+    fn gen<R: Rng>(rng: &mut R) -> Self {
+        if rng.gen() {
+            DiffusionMode::Relaxation
+        } else {
+            DiffusionMode::Conservative
+        }
+    }
+}
+
+impl Crossover for DiffusionMode {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        if rng.gen() {
+            *self
+        } else {
+            *other
+        }
+    }
+}
+
+impl Mutate for DiffusionMode {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        if rng.gen::<f32>() < rate {
+            *self = Self::gen(rng);
+        }
+    }
+}
+
+/// How pressure is found from the forces on the tiles.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PressureMethod {
+    /// Sums how strongly the forces pushing into each tile oppose each other, and pushes tiles
+    /// toward their lowest-pressure neighbor. Cheap, but nothing stays incompressible.
+    #[default]
+    Heuristic,
+    /// Solves for the pressure that cancels the divergence of the forces, relaxing every cell
+    /// part of the way from the last iteration's values.
+    Jacobi,
+    /// Solves like [`PressureMethod::Jacobi`], relaxing cells with even `x + y` first and the
+    /// rest from their already relaxed neighbors. Converges about twice as fast on a square
+    /// lattice. On a hexagonal one, and across periodic edges of odd length, neighbors of the same
+    /// color relax together as in Jacobi, so it gains less.
+    RedBlackGaussSeidel,
+    /// Solves like [`PressureMethod::RedBlackGaussSeidel`], correcting each iteration from
+    /// successively coarser grids so that pressure spreads across large worlds in a few
+    /// iterations.
+    Multigrid,
+}
+
+/// The pressure solve and when it stops.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PressureSolver {
+    pub method: PressureMethod,
+    /// Solving stops once no cell's equation is off by more than this fraction of the largest
+    /// divergence. Unused by the heuristic.
+    pub tolerance: ClampedF32<0, 1, 1>,
+    /// Solving stops after this many iterations even if it hasn't converged. Unused by the
+    /// heuristic.
+    pub max_iterations: usize,
+}
+
+impl Default for PressureSolver {
+    fn default() -> Self {
+        Self {
+            method: PressureMethod::default(),
+            tolerance: ClampedF32::new(0.01),
+            max_iterations: 100,
+        }
+    }
+}
+
+crate::impl_not_evolved!(PressureSolver);
+
+/// Tile temperature in degrees Celsius.
+pub type Temperature = ClampedF32<-100, 200, 1>;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Crossover, Mutate, Gen)]
 pub struct ElementConfig {
     pub adhesion: Polynomial,
     pub cohesion: Polynomial,
     pub density: Polynomial,
+    /// Energy needed to change the temperature of a tile by one degree.
+    pub heat_capacity: ClampedF32<1, 10, 1>,
+    /// How readily heat flows between this element and its neighbors.
+    pub conductivity: ClampedF32<0, 1, 1>,
+}
+
+/// The elements a world can contain. An [`Element`] is an index into this list, so the order of
+/// the definitions is significant.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "Vec<ElementDefinition>", into = "Vec<ElementDefinition>")]
+pub struct ElementRegistry(Vec<ElementDefinition>);
+
+impl ElementRegistry {
+    /// Fails if there are more definitions than an [`Element`] can index, or if an absorption or
+    /// transition names an element that isn't among them.
+    pub fn new(definitions: Vec<ElementDefinition>) -> Result<Self, ConfigError> {
+        if definitions.len() > u8::MAX as usize + 1 {
+            return Err(ConfigError::TooManyElements(definitions.len()));
+        }
+        let registry = Self(definitions);
+        for d in registry.0.iter() {
+            if let Some(a) = &d.absorption {
+                registry.resolve(&a.source, || format!("The absorption of {}", d.name))?;
+            }
+            for t in d.transitions.iter() {
+                registry.resolve(&t.into, || format!("A transition of {}", d.name))?;
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, element: Element) -> &ElementDefinition {
+        &self.0[element.index()]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Element, &ElementDefinition)> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (Element::from_index(i), d))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Element> {
+        self.0
+            .iter()
+            .position(|d| d.name == name)
+            .map(Element::from_index)
+    }
+
+    /// Like [`ElementRegistry::lookup`], but fails with an error naming the part of the config,
+    /// described by `context`, that refers to the element.
+    pub fn resolve(
+        &self,
+        name: &str,
+        context: impl FnOnce() -> String,
+    ) -> Result<Element, ConfigError> {
+        self.lookup(name)
+            .ok_or_else(|| ConfigError::UnknownElement {
+                name: name.to_string(),
+                context: context(),
+            })
+    }
+
+    /// The first of the tile's element's transitions whose conditions all hold, if any, along
+    /// with the element it turns the tile into. Transitions that would leave the tile as it is,
+    /// like soil baking dry when it is already dry, are passed over.
+    pub fn transition_for(&self, tile: &Tile) -> Option<(Element, &Transition)> {
+        self.get(tile.element())
+            .transitions
+            .iter()
+            .filter(|t| t.applies_to(tile))
+            .map(|t| {
+                let element = self
+                    .lookup(&t.into)
+                    .expect("Transition targets are checked when the registry is built");
+                (element, t)
+            })
+            .find(|(element, t)| {
+                *element != tile.element()
+                    || t.saturation
+                        .is_some_and(|s| s.as_f32() != tile.saturation().0)
+            })
+    }
+
+    /// For each element in order, the element it absorbs moisture from and how, if it absorbs at
+    /// all.
+    pub fn absorbers(&self) -> Vec<Option<(Element, &Absorption)>> {
+        self.0
+            .iter()
+            .map(|d| {
+                d.absorption.as_ref().map(|a| {
+                    let source = self
+                        .lookup(&a.source)
+                        .expect("Absorption sources are checked when the registry is built");
+                    (source, a)
+                })
+            })
+            .collect()
+    }
+
+    fn same_shape(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.0.iter().zip(other.0.iter()).all(|(a, b)| {
+                a.name == b.name
+                    && a.absorption.as_ref().map(|a| &a.source)
+                        == b.absorption.as_ref().map(|b| &b.source)
+                    && a.transitions.len() == b.transitions.len()
+                    && a.transitions
+                        .iter()
+                        .zip(b.transitions.iter())
+                        .all(|(ta, tb)| ta.into == tb.into && ta.when.len() == tb.when.len())
+            })
+    }
+}
+
+impl TryFrom<Vec<ElementDefinition>> for ElementRegistry {
+    type Error = ConfigError;
+
+    fn try_from(definitions: Vec<ElementDefinition>) -> Result<Self, ConfigError> {
+        Self::new(definitions)
+    }
+}
+
+impl From<ElementRegistry> for Vec<ElementDefinition> {
+    fn from(registry: ElementRegistry) -> Self {
+        registry.0
+    }
+}
+
+impl Default for ElementRegistry {
+    fn default() -> Self {
+        Self::new(vec![
+            ElementDefinition {
+                name: "air".to_string(),
+                color: [221, 255, 247],
+                wet_color: [165, 206, 213],
+                spawn: Some(Spawn {
+                    min_saturation: ClampedF32::new(0.5),
+                    max_saturation: ClampedF32::new(0.75),
+                    min_temperature: Temperature::new(10.0),
+                    max_temperature: Temperature::new(30.0),
+                }),
+                config: ElementConfig {
+                    adhesion: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(0.05)]),
+                    cohesion: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(0.4)]),
+                    density: Polynomial::new(vec![ClampedF32::new(0.1), ClampedF32::new(-0.99)]),
+                    heat_capacity: ClampedF32::new(1.0),
+                    conductivity: ClampedF32::new(0.1),
+                },
+                absorption: None,
+                transitions: vec![Transition {
+                    into: "water".to_string(),
+                    when: vec![Condition::SaturationAtLeast(ClampedF32::new(0.9))],
+                    saturation: None,
+                }],
+            },
+            ElementDefinition {
+                name: "soil".to_string(),
+                color: [169, 113, 75],
+                wet_color: [123, 81, 53],
+                spawn: Some(Spawn {
+                    min_saturation: ClampedF32::new(0.5),
+                    max_saturation: ClampedF32::new(0.9),
+                    min_temperature: Temperature::new(10.0),
+                    max_temperature: Temperature::new(30.0),
+                }),
+                config: ElementConfig {
+                    adhesion: Polynomial::new(vec![
+                        ClampedF32::new(0.0),
+                        ClampedF32::new(3.25),
+                        ClampedF32::new(-2.5),
+                    ]),
+                    cohesion: Polynomial::new(vec![
+                        ClampedF32::new(0.0),
+                        ClampedF32::new(3.25),
+                        ClampedF32::new(-2.5),
+                    ]),
+                    density: Polynomial::new(vec![ClampedF32::new(1.0), ClampedF32::new(-0.1)]),
+                    heat_capacity: ClampedF32::new(2.0),
+                    conductivity: ClampedF32::new(0.5),
+                },
+                absorption: Some(Absorption {
+                    source: "water".to_string(),
+                    capacity: ClampedF32::new(0.9),
+                    rate: ClampedF32::new(0.2),
+                    wicking_rate: ClampedF32::new(0.2),
+                }),
+                // Soil bakes dry once it gets hot enough.
+                transitions: vec![Transition {
+                    into: "soil".to_string(),
+                    when: vec![Condition::TemperatureAtLeast(Temperature::new(120.0))],
+                    saturation: Some(ClampedF32::new(0.0)),
+                }],
+            },
+            ElementDefinition {
+                name: "water".to_string(),
+                color: [221, 255, 247],
+                wet_color: [46, 134, 171],
+                spawn: Some(Spawn {
+                    min_saturation: ClampedF32::new(1.0),
+                    max_saturation: ClampedF32::new(1.0),
+                    min_temperature: Temperature::new(5.0),
+                    max_temperature: Temperature::new(25.0),
+                }),
+                config: ElementConfig {
+                    adhesion: Polynomial::new(vec![ClampedF32::new(0.75)]),
+                    cohesion: Polynomial::new(vec![ClampedF32::new(0.5)]),
+                    density: Polynomial::new(vec![ClampedF32::new(0.5), ClampedF32::new(0.1)]),
+                    heat_capacity: ClampedF32::new(4.0),
+                    conductivity: ClampedF32::new(0.6),
+                },
+                absorption: None,
+                transitions: vec![
+                    Transition {
+                        into: "air".to_string(),
+                        when: vec![Condition::SaturationBelow(ClampedF32::new(0.5))],
+                        saturation: None,
+                    },
+                    Transition {
+                        into: "ice".to_string(),
+                        when: vec![Condition::TemperatureBelow(Temperature::new(0.0))],
+                        saturation: None,
+                    },
+                    Transition {
+                        into: "vapor".to_string(),
+                        when: vec![Condition::TemperatureAtLeast(Temperature::new(100.0))],
+                        saturation: None,
+                    },
+                ],
+            },
+            ElementDefinition {
+                name: "ice".to_string(),
+                color: [232, 244, 250],
+                wet_color: [186, 222, 240],
+                spawn: None,
+                config: ElementConfig {
+                    adhesion: Polynomial::new(vec![ClampedF32::new(0.5)]),
+                    cohesion: Polynomial::new(vec![ClampedF32::new(2.0)]),
+                    density: Polynomial::new(vec![ClampedF32::new(0.45), ClampedF32::new(0.1)]),
+                    heat_capacity: ClampedF32::new(2.0),
+                    conductivity: ClampedF32::new(0.9),
+                },
+                absorption: None,
+                transitions: vec![Transition {
+                    into: "water".to_string(),
+                    when: vec![Condition::TemperatureAtLeast(Temperature::new(0.0))],
+                    saturation: None,
+                }],
+            },
+            ElementDefinition {
+                name: "vapor".to_string(),
+                color: [240, 240, 240],
+                wet_color: [200, 210, 215],
+                spawn: None,
+                config: ElementConfig {
+                    adhesion: Polynomial::new(vec![ClampedF32::new(0.05)]),
+                    cohesion: Polynomial::new(vec![ClampedF32::new(0.1)]),
+                    density: Polynomial::new(vec![ClampedF32::new(0.05)]),
+                    heat_capacity: ClampedF32::new(2.0),
+                    conductivity: ClampedF32::new(0.05),
+                },
+                absorption: None,
+                transitions: vec![Transition {
+                    into: "water".to_string(),
+                    when: vec![Condition::TemperatureBelow(Temperature::new(100.0))],
+                    saturation: None,
+                }],
+            },
+        ])
+        .expect("The default elements only refer to each other")
+    }
+}
+
+/// Generates the default set of elements with random parameters. The element names, colors and
+/// transition targets are structural and are never evolved.
+impl Gen for ElementRegistry {
+    fn gen<R: Rng>(rng: &mut R) -> Self {
+        let mut registry = Self::default();
+        for d in registry.0.iter_mut() {
+            d.config = ElementConfig::gen(rng);
+            if let Some(a) = d.absorption.as_mut() {
+                a.regen(rng);
+            }
+            for t in d.transitions.iter_mut() {
+                for c in t.when.iter_mut() {
+                    c.regen(rng);
+                }
+            }
+        }
+        registry
+    }
+}
+
+impl Crossover for ElementRegistry {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        if !self.same_shape(other) {
+            return if rng.gen() {
+                self.clone()
+            } else {
+                other.clone()
+            };
+        }
+        Self(
+            self.0
+                .iter()
+                .zip(other.0.iter())
+                .map(|(a, b)| a.crossover(b, rng))
+                .collect(),
+        )
+    }
+}
+
+impl Mutate for ElementRegistry {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        for d in self.0.iter_mut() {
+            d.mutate(rate, rng);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ElementDefinition {
+    pub name: String,
+    /// sRGB color of the element when completely dry.
+    pub color: [u8; 3],
+    /// sRGB color of the element when completely saturated.
+    pub wet_color: [u8; 3],
+    /// How the element is placed in a freshly generated world, or `None` if it only appears
+    /// through transitions.
+    pub spawn: Option<Spawn>,
+    pub config: ElementConfig,
+    pub absorption: Option<Absorption>,
+    pub transitions: Vec<Transition>,
+}
+
+impl Crossover for ElementDefinition {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        Self {
+            config: self.config.crossover(&other.config, rng),
+            absorption: match (&self.absorption, &other.absorption) {
+                (Some(a), Some(b)) => Some(a.crossover(b, rng)),
+                _ => self.absorption.clone(),
+            },
+            transitions: self
+                .transitions
+                .iter()
+                .zip(other.transitions.iter())
+                .map(|(a, b)| a.crossover(b, rng))
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Mutate for ElementDefinition {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        self.config.mutate(rate, rng);
+        if let Some(a) = self.absorption.as_mut() {
+            a.mutate(rate, rng);
+        }
+        for t in self.transitions.iter_mut() {
+            t.mutate(rate, rng);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Spawn {
+    pub min_saturation: ClampedF32<0, 1, 1>,
+    pub max_saturation: ClampedF32<0, 1, 1>,
+    pub min_temperature: Temperature,
+    pub max_temperature: Temperature,
+}
+
+/// Lets an element soak up moisture from neighboring tiles of the element named `source`, and
+/// wick it on to neighboring tiles of its own element.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Absorption {
+    pub source: String,
+    /// Saturation above which the element stops absorbing.
+    pub capacity: ClampedF32<0, 1, 1>,
+    /// Fraction of a source neighbor's moisture taken each update, spread across all neighbors.
+    pub rate: ClampedF32<0, 1, 1>,
+    /// How quickly moisture evens out between neighboring tiles of this element.
+    pub wicking_rate: ClampedF32<0, 1, 1>,
+}
+
+impl Absorption {
+    fn regen<R: Rng>(&mut self, rng: &mut R) {
+        self.capacity = ClampedF32::gen(rng);
+        self.rate = ClampedF32::gen(rng);
+        self.wicking_rate = ClampedF32::gen(rng);
+    }
+}
+
+impl Crossover for Absorption {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        Self {
+            source: self.source.clone(),
+            capacity: self.capacity.crossover(&other.capacity, rng),
+            rate: self.rate.crossover(&other.rate, rng),
+            wicking_rate: self.wicking_rate.crossover(&other.wicking_rate, rng),
+        }
+    }
+}
+
+impl Mutate for Absorption {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        self.capacity.mutate(rate, rng);
+        self.rate.mutate(rate, rng);
+        self.wicking_rate.mutate(rate, rng);
+    }
+}
+
+/// Turns a tile into the element named `into` once every condition in `when` holds, optionally
+/// resetting its saturation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Transition {
+    pub into: String,
+    pub when: Vec<Condition>,
+    pub saturation: Option<ClampedF32<0, 1, 1>>,
+}
+
+impl Transition {
+    pub fn applies_to(&self, tile: &Tile) -> bool {
+        self.when.iter().all(|c| c.holds_for(tile))
+    }
+}
+
+impl Crossover for Transition {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        Self {
+            into: self.into.clone(),
+            saturation: self.saturation,
+            when: self
+                .when
+                .iter()
+                .zip(other.when.iter())
+                .map(|(a, b)| a.crossover(b, rng))
+                .collect(),
+        }
+    }
+}
+
+impl Mutate for Transition {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        for c in self.when.iter_mut() {
+            c.mutate(rate, rng);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Condition {
+    SaturationAtLeast(ClampedF32<0, 1, 1>),
+    SaturationBelow(ClampedF32<0, 1, 1>),
+    TemperatureAtLeast(Temperature),
+    TemperatureBelow(Temperature),
+}
+
+impl Condition {
+    pub fn holds_for(&self, tile: &Tile) -> bool {
+        match self {
+            Condition::SaturationAtLeast(t) => tile.saturation().0 >= t.as_f32(),
+            Condition::SaturationBelow(t) => tile.saturation().0 < t.as_f32(),
+            Condition::TemperatureAtLeast(t) => tile.temperature().0 >= t.as_f32(),
+            Condition::TemperatureBelow(t) => tile.temperature().0 < t.as_f32(),
+        }
+    }
+
+    /// Replaces the threshold with a random one, keeping the kind of condition.
+    fn regen<R: Rng>(&mut self, rng: &mut R) {
+        match self {
+            Condition::SaturationAtLeast(t) | Condition::SaturationBelow(t) => {
+                *t = ClampedF32::gen(rng)
+            }
+            Condition::TemperatureAtLeast(t) | Condition::TemperatureBelow(t) => {
+                *t = Temperature::gen(rng)
+            }
+        }
+    }
+}
+
+impl Crossover for Condition {
+    fn crossover<R: Rng>(&self, other: &Self, rng: &mut R) -> Self {
+        match (self, other) {
+            (Condition::SaturationAtLeast(a), Condition::SaturationAtLeast(b)) => {
+                Condition::SaturationAtLeast(a.crossover(b, rng))
+            }
+            (Condition::SaturationBelow(a), Condition::SaturationBelow(b)) => {
+                Condition::SaturationBelow(a.crossover(b, rng))
+            }
+            (Condition::TemperatureAtLeast(a), Condition::TemperatureAtLeast(b)) => {
+                Condition::TemperatureAtLeast(a.crossover(b, rng))
+            }
+            (Condition::TemperatureBelow(a), Condition::TemperatureBelow(b)) => {
+                Condition::TemperatureBelow(a.crossover(b, rng))
+            }
+            _ => {
+                if rng.gen() {
+                    *self
+                } else {
+                    *other
+                }
+            }
+        }
+    }
+}
+
+impl Mutate for Condition {
+    fn mutate<R: Rng>(&mut self, rate: f32, rng: &mut R) {
+        match self {
+            Condition::SaturationAtLeast(t) | Condition::SaturationBelow(t) => t.mutate(rate, rng),
+            Condition::TemperatureAtLeast(t) | Condition::TemperatureBelow(t) => {
+                t.mutate(rate, rng)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ordered_float::OrderedFloat;
+
+    use super::*;
+    use crate::simulation::boundary::{Boundary, BoundaryTile};
+
+    #[test]
+    fn the_newer_fields_default_when_missing() {
+        let mut json = serde_json::to_value(Config::default()).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        for field in [
+            "boundaries",
+            "neighborhood",
+            "topology",
+            "traversal",
+            "arbiter",
+            "swap_min_density_difference",
+            "movement",
+            "resolver",
+            "pressure",
+        ] {
+            assert!(
+                fields.remove(field).is_some(),
+                "{field} should be serialized"
+            );
+        }
+
+        let config: Config = serde_json::from_value(json).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn configs_naming_unknown_elements_fail_to_load() {
+        let load = |config: &Config| {
+            let json = serde_json::to_string(config).unwrap();
+            serde_json::from_str::<Config>(&json)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let mut transition = Config::default();
+        transition.elements.0[0].transitions[0].into = "lava".to_string();
+        assert!(
+            load(&transition).contains("A transition of air refers to unknown element \"lava\"")
+        );
+
+        let mut absorption = Config::default();
+        absorption.elements.0[1].absorption.as_mut().unwrap().source = "lava".to_string();
+        assert!(load(&absorption).contains("The absorption of soil"));
+
+        let boundary = Config {
+            boundaries: Boundaries {
+                top: Boundary::Open(BoundaryTile {
+                    element: "lava".to_string(),
+                    saturation: ClampedF32::new(0.0),
+                    temperature: Temperature::new(20.0),
+                }),
+                ..Boundaries::default()
+            },
+            ..Config::default()
+        };
+        assert!(load(&boundary).contains("The Top boundary"));
+        assert_eq!(
+            boundary.check(),
+            Err(ConfigError::UnknownElement {
+                name: "lava".to_string(),
+                context: "The Top boundary".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn transitions_that_would_leave_the_tile_unchanged_do_not_fire() {
+        let elements = ElementRegistry::default();
+        let soil = elements.lookup("soil").unwrap();
+        let hot_soil = |saturation| Tile {
+            element: soil,
+            saturation: OrderedFloat(saturation),
+            temperature: OrderedFloat(150.0),
+        };
+
+        let (element, transition) = elements.transition_for(&hot_soil(0.5)).unwrap();
+        assert_eq!(element, soil);
+        assert_eq!(transition.saturation, Some(ClampedF32::new(0.0)));
+        assert_eq!(elements.transition_for(&hot_soil(0.0)), None);
+    }
+
+    #[test]
+    fn asymmetric_neighborhoods_are_rejected() {
+        let with_mask = |mask: Vec<(isize, isize)>| Config {
+            neighborhood: Neighborhood::Mask(mask),
+            ..Config::default()
+        };
+        assert_eq!(
+            with_mask(vec![(1, 0), (-1, 0), (0, 2), (0, -2)]).check(),
+            Ok(())
+        );
+
+        let lopsided = with_mask(vec![(1, 0), (-1, 0), (0, 1)]);
+        assert_eq!(lopsided.check(), Err(ConfigError::AsymmetricNeighborhood));
+        let json = serde_json::to_string(&lopsided).unwrap();
+        assert!(serde_json::from_str::<Config>(&json).is_err());
+    }
 }
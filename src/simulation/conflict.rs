@@ -1,31 +1,296 @@
-use ordered_float::OrderedFloat;
+mod auction;
+
+use std::f32::consts::FRAC_PI_4;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
 use crate::grid::{Grid, GridEnumerator, GridLike};
 
-use super::ForceField;
+use super::{
+    arbiter::{Contest, MoveArbiter},
+    error::SimulationError,
+};
 
+/// Where the tile that ends up in a cell comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The tile at these coordinates moves here.
+    Tile(isize, isize),
+    /// The tile at `tile` moves here along its path, whose first step is into `step`.
+    Path {
+        tile: (isize, isize),
+        step: (isize, isize),
+    },
+    /// Nothing moved here, so the cell is filled from the open boundary that the tile at `tile`
+    /// left the world through, toward the out-of-bounds coordinates `target`. That tile is either
+    /// the one that was here, or the last of a line of tiles that each moved into the cell of the
+    /// next, starting with the one that was here.
+    Exited {
+        tile: (isize, isize),
+        target: (isize, isize),
+    },
+}
+
+impl Source {
+    /// Where the move into the cell at `(x, y)`, or the move out of the world that left it empty,
+    /// starts and ends. A move along a path ends at its first step instead, since the path's far
+    /// end can lie across several periodic edges.
+    fn endpoints(&self, x: usize, y: usize) -> ((isize, isize), (isize, isize)) {
+        match *self {
+            Source::Tile(ox, oy) => ((ox, oy), (x as isize, y as isize)),
+            Source::Path { tile, step } => (tile, step),
+            Source::Exited { tile, target } => (tile, target),
+        }
+    }
+}
+
+/// How the tiles that want the same cell are sorted out.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Resolver {
+    /// Gives every contested cell to the tile `arbiter` picks and sends the others on to their
+    /// next choice, round after round until nothing is contested. Cells are visited in the
+    /// configured traversal order, and tiles claim cells in that order, so when the arbiter finds
+    /// tiles tied, the one visited first wins. The random arbiters break ties at random instead.
+    #[default]
+    Greedy,
+    /// Assigns tiles to cells so that the moves line up with the tiles' forces as well as
+    /// possible overall, using an auction. Ignores the arbiter and the traversal order.
+    Auction,
+}
+
+crate::impl_not_evolved!(Resolver);
+
+/// Settles which tile ends up in every cell, using `resolver`. `order` should list every cell of
+/// the grid once, and is the order the greedy resolver visits cells in; `arbiter` decides between
+/// the tiles that want the same cell in it. Statistics about the moves are only gathered when
+/// `record_stats` is set.
+///
+/// Neighboring tiles can also trade places when [`Contest::favors_swap`] allows it: first, before
+/// any other move is considered, and later, when a tile has lost every cell it could move to,
+/// including its own, to the tile whose cell it would take. Before either, tiles with a path
+/// longer than one cell travel along it as far as they could swap their way through, and every
+/// tile they pass moves back one cell along the path.
+///
+/// Every tile that leaves the world through an open boundary leaves one cell empty, either its
+/// own or, when tiles move up to take its place, the cell at the far end of that line. Fails if
+/// some cell is left empty without a tile having left.
 pub fn reduce_potential_moves(
-    forces: &ForceField,
+    resolver: Resolver,
+    arbiter: &impl MoveArbiter,
+    contest: &Contest,
     potential_moves: &mut Grid<PotentialMoves>,
-) -> (Grid<(isize, isize)>, usize) {
-    let mut iters = 0;
-    let mut conflicts = Grid::new(
+    order: &[(usize, usize)],
+    rng: &mut dyn RngCore,
+    record_stats: bool,
+) -> Result<Resolution, SimulationError> {
+    let first_choices = record_stats.then(|| potential_moves.map(|p| p.current()));
+    let mut resolutions = Grid::new(
         potential_moves.width(),
         potential_moves.height(),
         |_x, _y| MoveConflict::new(),
     );
-    let resolutions = loop {
+    lock_paths(contest, &mut resolutions, potential_moves, order);
+    lock_swaps(contest, &mut resolutions, potential_moves, order);
+    let (iterations, bids) = match resolver {
+        Resolver::Greedy => (
+            resolve_greedily(
+                arbiter,
+                contest,
+                &mut resolutions,
+                potential_moves,
+                order,
+                rng,
+            ),
+            0,
+        ),
+        Resolver::Auction => (
+            1,
+            auction::assign(contest, &mut resolutions, potential_moves),
+        ),
+    };
+
+    let (width, height) = (potential_moves.width(), potential_moves.height());
+    // Every move is settled by now and each cell's source is read off them without changing
+    // anything, so this pass doesn't depend on `order`. It walks the cells row-major because that
+    // is the layout `Grid::from_cells` expects.
+    let sources = GridEnumerator::new(potential_moves)
+        .map(|(x, y)| {
+            let (x, y) = (x as isize, y as isize);
+            let r = resolutions.get(x, y).unwrap();
+            if r.is_resolved() {
+                let (ox, oy) = r.resolved_move();
+                return Ok(match r.step {
+                    Some(step) => Source::Path {
+                        tile: (ox, oy),
+                        step,
+                    },
+                    None => Source::Tile(ox, oy),
+                });
+            }
+            match exit_from((x, y), &resolutions, potential_moves) {
+                Some(source) => Ok(source),
+                None => Err(SimulationError::UnresolvedConflict {
+                    x,
+                    y,
+                    candidates: potential_moves
+                        .enumerate()
+                        .filter(|(_, _, p)| p.preferences.contains(&(x, y)))
+                        .map(|(cx, cy, _)| (cx as isize, cy as isize))
+                        .collect(),
+                    iterations,
+                }),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    let sources = Grid::from_cells(width, height, sources);
+    let stats = first_choices.map(|first_choices| {
+        let claimants = resolutions.map(|r| r.claims);
+        MovementStats::new(contest, &first_choices, &sources, claimants)
+    });
+    Ok(Resolution {
+        sources,
+        iterations,
+        bids,
+        stats,
+    })
+}
+
+/// Follows the moves out of the empty cell at `cell`, each tile into the cell of the next, to the
+/// tile at the end of the line that left the world. `None` if the line ends with a tile that
+/// didn't get the cell it moves to. Each cell has at most one tile moving in, so every empty cell
+/// leads to a different tile.
+fn exit_from(
+    cell: (isize, isize),
+    conflicts: &Grid<MoveConflict>,
+    potential_moves: &Grid<PotentialMoves>,
+) -> Option<Source> {
+    let mut from = cell;
+    loop {
+        let to = potential_moves.get(from.0, from.1).unwrap().current()?;
+        match conflicts.get(to.0, to.1) {
+            None => {
+                return Some(Source::Exited {
+                    tile: from,
+                    target: to,
+                })
+            }
+            Some(c) if to != from && c.is_resolved() && c.resolved_move() == from => from = to,
+            Some(_) => return None,
+        }
+    }
+}
+
+/// How the moves of one update were settled.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// Where every cell's tile comes from.
+    pub sources: Grid<Source>,
+    /// How many rounds the greedy resolver took. The auction settles everything in one.
+    pub iterations: usize,
+    /// How many bids the auction took, or zero for the greedy resolver.
+    pub bids: usize,
+    /// What happened to the tiles, if it was asked for.
+    pub stats: Option<MovementStats>,
+}
+
+/// How well the moves in `sources` line up with the forces on the tiles making them, in total.
+pub fn alignment(contest: &Contest, sources: &Grid<Source>) -> f32 {
+    sources
+        .enumerate()
+        .map(|(x, y, source)| {
+            let (from, to) = source.endpoints(x, y);
+            contest
+                .force(from.0, from.1)
+                .dot(&contest.direction(from, to))
+        })
+        .sum()
+}
+
+/// What happened to the tiles during one update's movement.
+#[derive(Debug, Clone)]
+pub struct MovementStats {
+    /// Tiles that ended the update in a different cell, including tiles that left the world.
+    pub moved: usize,
+    /// Tiles that wanted to move most but stayed in their cell.
+    pub locked: usize,
+    /// How many tiles moved in each direction, in eight sectors 45 degrees wide clockwise from
+    /// +x, so that index 2 counts tiles that moved down.
+    pub directions: [usize; 8],
+    /// How many times tiles claimed each cell, including its own tile claiming it to stay. Every
+    /// cell a tile tries, in any round or along its path, counts once for as long as the tile
+    /// keeps trying it, and every bid for the cell counts in an auction.
+    pub claimants: Grid<u32>,
+}
+
+impl MovementStats {
+    fn new(
+        contest: &Contest,
+        first_choices: &Grid<Option<(isize, isize)>>,
+        sources: &Grid<Source>,
+        claimants: Grid<u32>,
+    ) -> Self {
+        let (mut moved, mut locked, mut directions) = (0, 0, [0; 8]);
+        for (x, y, source) in sources.enumerate() {
+            let (from, to) = source.endpoints(x, y);
+            if from != to {
+                moved += 1;
+                let d = contest.direction(from, to);
+                let sector = (d.y.atan2(d.x) / FRAC_PI_4).round().rem_euclid(8.0) as usize;
+                directions[sector % 8] += 1;
+            } else if first_choices
+                .get(x as isize, y as isize)
+                .unwrap()
+                .is_some_and(|choice| choice != from)
+            {
+                locked += 1;
+            }
+        }
+
+        Self {
+            moved,
+            locked,
+            directions,
+            claimants,
+        }
+    }
+}
+
+/// Resolves conflicts round after round, returning how many rounds it took.
+fn resolve_greedily(
+    arbiter: &impl MoveArbiter,
+    contest: &Contest,
+    conflicts: &mut Grid<MoveConflict>,
+    potential_moves: &mut Grid<PotentialMoves>,
+    order: &[(usize, usize)],
+    rng: &mut dyn RngCore,
+) -> usize {
+    // The cell each tile last claimed, so a tile that keeps claiming the same cell round after
+    // round is only counted once.
+    let mut claimed = Grid::new(conflicts.width(), conflicts.height(), |_, _| None);
+    let mut iters = 0;
+    loop {
         iters += 1;
-        for (x, y) in GridEnumerator::new(&conflicts) {
+        for &(x, y) in order {
             let c = conflicts.get_mut(x as isize, y as isize).unwrap();
             if !c.is_locked() {
                 c.reset();
             }
         }
-        for (x, y) in GridEnumerator::new(potential_moves) {
+        for &(x, y) in order {
             let p = potential_moves.get_mut(x as isize, y as isize).unwrap();
+            let last_claimed = claimed.get_mut(x as isize, y as isize).unwrap();
             while let Some((new_x, new_y)) = p.current() {
-                let c = conflicts.get_mut(new_x, new_y).unwrap();
+                // Targets outside the grid are exits through an open boundary, which never
+                // conflict.
+                let Some(c) = conflicts.get_mut(new_x, new_y) else {
+                    break;
+                };
+                if *last_claimed != Some((new_x, new_y)) {
+                    *last_claimed = Some((new_x, new_y));
+                    c.claims += 1;
+                }
                 if !c.push_move((x as isize, y as isize)) {
                     p.pop();
                 } else {
@@ -33,78 +298,169 @@ pub fn reduce_potential_moves(
                 }
             }
         }
-        let mut found_conflicts = resolve_conflicts(forces, &mut conflicts, potential_moves);
-        for (x, y) in GridEnumerator::new(potential_moves) {
-            let p = potential_moves.get_mut(x as isize, y as isize).unwrap();
-            if p.current().is_none() {
-                let c = conflicts.get_mut(x as isize, y as isize).unwrap();
-                if !c.is_locked() {
-                    c.lock((x as isize, y as isize));
-                    found_conflicts = true;
-                }
+        let mut found_conflicts =
+            resolve_conflicts(arbiter, contest, conflicts, potential_moves, order, rng);
+        for &(x, y) in order {
+            let here = (x as isize, y as isize);
+            let c = conflicts.get(here.0, here.1).unwrap();
+            if potential_moves
+                .get(here.0, here.1)
+                .unwrap()
+                .current()
+                .is_some()
+                || c.is_locked()
+            {
+                continue;
+            }
+            // The tile here has lost every cell, its own included. Rather than taking its cell
+            // back, it can trade places with the tile that took it.
+            let displacer = c
+                .is_resolved()
+                .then(|| c.resolved_move())
+                .filter(|&(dx, dy)| {
+                    !conflicts.get(dx, dy).unwrap().is_locked()
+                        && contest.favors_swap((dx, dy), here)
+                });
+            match displacer {
+                Some(displacer) => rotate(conflicts, potential_moves, &[displacer, here]),
+                None => conflicts.get_mut(here.0, here.1).unwrap().lock(here),
             }
+            found_conflicts = true;
         }
         if !found_conflicts {
-            break conflicts;
+            break iters;
         }
-    };
-    // println!("Conflict resolution iterations: {iters}");
+    }
+}
 
-    (
-        Grid::new(potential_moves.width(), potential_moves.height(), |x, y| {
-            let r = resolutions.get(x as isize, y as isize).unwrap();
-            if r.is_resolved() {
-                let (ox, oy) = r.resolved_move();
-                (ox, oy)
-            } else {
-                panic!("Unresolved conflict");
-            }
-        }),
-        iters,
-    )
+/// Swaps every tile with the first cell it would rather move to than stay put in whose tile it
+/// can swap with, skipping tiles that have already swapped.
+fn lock_swaps(
+    contest: &Contest,
+    conflicts: &mut Grid<MoveConflict>,
+    potential_moves: &mut Grid<PotentialMoves>,
+    order: &[(usize, usize)],
+) {
+    for &(x, y) in order {
+        let a = (x as isize, y as isize);
+        if conflicts.get(a.0, a.1).unwrap().is_locked() {
+            continue;
+        }
+        let partner = potential_moves
+            .get(a.0, a.1)
+            .unwrap()
+            .preferences
+            .iter()
+            .take_while(|&&target| target != a)
+            .copied()
+            .find(|&(bx, by)| {
+                conflicts.get(bx, by).is_some_and(|c| !c.is_locked())
+                    && contest.favors_swap(a, (bx, by))
+            });
+        if let Some(b) = partner {
+            rotate(conflicts, potential_moves, &[a, b]);
+        }
+    }
+}
+
+/// Moves every tile with a path at least two cells long to the furthest cell along it that it
+/// can swap its way to, skipping tiles that have already moved. Every swap along the way is judged
+/// in the direction of the path's first step.
+fn lock_paths(
+    contest: &Contest,
+    conflicts: &mut Grid<MoveConflict>,
+    potential_moves: &mut Grid<PotentialMoves>,
+    order: &[(usize, usize)],
+) {
+    for &(x, y) in order {
+        let a = (x as isize, y as isize);
+        if conflicts.get(a.0, a.1).unwrap().is_locked() {
+            continue;
+        }
+        let full_path = &potential_moves.get(a.0, a.1).unwrap().path;
+        let Some(&step) = full_path.first() else {
+            continue;
+        };
+        let direction = contest.direction(a, step);
+        let path: Vec<_> = full_path
+            .iter()
+            .copied()
+            .take_while(|&(px, py)| {
+                !conflicts.get(px, py).unwrap().is_locked()
+                    && contest.favors_swap_along(a, (px, py), direction)
+            })
+            .collect();
+        if path.len() > 1 {
+            // The tile lands at the end of the path, and the tiles it passed each fall back one
+            // cell toward where it started.
+            let (ex, ey) = path[path.len() - 1];
+            let cycle: Vec<_> = std::iter::once(a).chain(path.into_iter().rev()).collect();
+            rotate(conflicts, potential_moves, &cycle);
+            conflicts.get_mut(ex, ey).unwrap().step = Some(step);
+        }
+    }
+}
+
+/// Locks every tile in `cycle` into the cell of the next one, and the last tile into the cell of
+/// the first.
+fn rotate(
+    conflicts: &mut Grid<MoveConflict>,
+    potential_moves: &mut Grid<PotentialMoves>,
+    cycle: &[(isize, isize)],
+) {
+    for (i, &(x, y)) in cycle.iter().enumerate() {
+        let (tx, ty) = cycle[(i + 1) % cycle.len()];
+        conflicts.get_mut(tx, ty).unwrap().lock((x, y));
+        *potential_moves.get_mut(x, y).unwrap() = PotentialMoves::new(vec![(tx, ty)]);
+    }
 }
 
 pub fn resolve_conflicts(
-    forces: &ForceField,
+    arbiter: &impl MoveArbiter,
+    contest: &Contest,
     conflicts: &mut Grid<MoveConflict>,
     potential_moves: &mut Grid<PotentialMoves>,
+    order: &[(usize, usize)],
+    rng: &mut dyn RngCore,
 ) -> bool {
     let mut found = false;
-    for (x, y) in GridEnumerator::new(conflicts) {
+    for &(x, y) in order {
         let c = conflicts.get_mut(x as isize, y as isize).unwrap();
         if c.is_in_conflict() {
             found = true;
 
-            let (winner_index, _) = c
-                .iter()
-                .enumerate()
-                .max_by_key(|(_, (cx, cy))| OrderedFloat(forces.get(*cx, *cy).unwrap().norm()))
-                .unwrap();
+            let winner_index = arbiter.pick(contest, (x as isize, y as isize), &c.candidates, rng);
 
-            c.swap_remove(winner_index);
+            let winner = c.swap_remove(winner_index);
 
             for (cx, cy) in c.iter() {
                 potential_moves.get_mut(*cx, *cy).unwrap().pop();
             }
+            c.resolve(winner);
         }
     }
 
     found
 }
 
+/// The tiles trying to move into a cell. There can be as many as the neighborhood has cells.
 #[derive(Debug, Clone)]
 pub struct MoveConflict {
-    candidates: [Option<(isize, isize)>; (3 * 3) as usize],
-    len: usize,
+    candidates: Vec<(isize, isize)>,
     locked: bool,
+    /// How many times tiles have claimed the cell, over every round.
+    claims: u32,
+    /// The first step of the path the tile moving here took, if it came along one.
+    step: Option<(isize, isize)>,
 }
 
 impl MoveConflict {
     fn new() -> Self {
         Self {
-            candidates: [None; (3 * 3) as usize],
-            len: 0,
+            candidates: vec![],
             locked: false,
+            claims: 0,
+            step: None,
         }
     }
 
@@ -113,37 +469,36 @@ impl MoveConflict {
     }
 
     fn is_resolved(&self) -> bool {
-        self.len == 1
+        self.candidates.len() == 1
     }
 
     fn is_in_conflict(&self) -> bool {
-        self.len > 1
+        self.candidates.len() > 1
     }
 
     fn iter(&self) -> impl Iterator<Item = &(isize, isize)> {
-        self.candidates
-            .iter()
-            .take(self.len)
-            .filter_map(|x| x.as_ref())
+        self.candidates.iter()
     }
 
     fn resolved_move(&self) -> (isize, isize) {
-        self.candidates[0].unwrap()
+        self.candidates[0]
     }
 
     fn push_move(&mut self, m: (isize, isize)) -> bool {
         if self.locked {
-            false
+            // A cell locked for a tile that moves into it, such as half of a swap, still accepts
+            // that tile.
+            self.candidates[0] == m
         } else {
-            self.candidates[self.len] = Some(m);
-            self.len += 1;
+            self.candidates.push(m);
             true
         }
     }
 
     fn reset(&mut self) {
-        self.len = 0;
+        self.candidates.clear();
         self.locked = false;
+        self.step = None;
     }
 
     fn resolve(&mut self, m: (isize, isize)) {
@@ -156,16 +511,19 @@ impl MoveConflict {
         self.locked = true;
     }
 
-    fn swap_remove(&mut self, i: usize) {
-        self.candidates[i] = self.candidates[self.len - 1];
-        self.len -= 1;
+    fn swap_remove(&mut self, i: usize) -> (isize, isize) {
+        self.candidates.swap_remove(i)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PotentialMoves {
     preferences: Vec<(isize, isize)>,
     current: usize,
+    /// The cells the tile would pass through on a move longer than one cell, ending where it
+    /// would stop. Empty if the tile can only move to a neighbor.
+    #[serde(default)]
+    path: Vec<(isize, isize)>,
 }
 
 impl PotentialMoves {
@@ -173,9 +531,15 @@ impl PotentialMoves {
         Self {
             current: 0,
             preferences,
+            path: vec![],
         }
     }
 
+    pub fn with_path(mut self, path: Vec<(isize, isize)>) -> PotentialMoves {
+        self.path = path;
+        self
+    }
+
     fn current(&self) -> Option<(isize, isize)> {
         self.preferences.get(self.current).cloned()
     }
@@ -184,3 +548,283 @@ impl PotentialMoves {
         self.current += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        grid::traversal::Traversal,
+        simulation::{arbiter::LargestForce, config::Config, ForceField, Tile},
+    };
+
+    type Cell<'a> = (&'a str, (f32, f32), &'a [(isize, isize)]);
+
+    const STILL: Cell = ("air", (0.0, 0.0), &[]);
+
+    /// The tiles at (0, 1) and (2, 1) both want (1, 1), and the tile at (1, 1) would rather move
+    /// right than left. The greedy resolver gives (1, 1) to the tile pushed hardest, which leaves
+    /// the tile at (1, 1) to lose (2, 1) to the tile already there and move left, against its
+    /// force. Giving (1, 1) to the other tile lets every moving tile move the way it is pushed.
+    const CONTESTED: &[&[Cell]] = &[
+        &[STILL; 3],
+        &[
+            ("air", (3.0, 0.0), &[(1, 1), (0, 1)]),
+            ("air", (1.0, 0.0), &[(2, 1), (0, 1), (1, 1)]),
+            ("air", (-2.0, 0.0), &[(1, 1), (2, 1)]),
+        ],
+        &[STILL; 3],
+    ];
+
+    /// The tiles of a test world, the forces on them and the cells they would like to move to.
+    struct World {
+        config: Config,
+        elements: Grid<Tile>,
+        forces: ForceField,
+        potential_moves: Grid<PotentialMoves>,
+    }
+
+    impl World {
+        /// The contest over the world's cells, and the moves it settles.
+        fn contest(&mut self) -> (Contest<'_>, &mut Grid<PotentialMoves>) {
+            let contest = Contest {
+                forces: &self.forces,
+                elements: &self.elements,
+                config: &self.config,
+            };
+            (contest, &mut self.potential_moves)
+        }
+
+        /// The cells in the order [`State::update`] would visit them with the row-major traversal.
+        ///
+        /// [`State::update`]: crate::simulation::State::update
+        fn order(&self) -> Vec<(usize, usize)> {
+            let (width, height) = (self.elements.width(), self.elements.height());
+            Traversal::RowMajor.order(width, height, 0, &mut rng())
+        }
+
+        /// Settles every move with `resolver`, leaving ties to [`LargestForce`].
+        fn resolve(&mut self, resolver: Resolver, record_stats: bool) -> Resolution {
+            let order = self.order();
+            let (contest, potential_moves) = self.contest();
+            reduce_potential_moves(
+                resolver,
+                &LargestForce,
+                &contest,
+                potential_moves,
+                &order,
+                &mut rng(),
+                record_stats,
+            )
+            .unwrap()
+        }
+    }
+
+    fn rng() -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(0)
+    }
+
+    /// A world with the default config of `rows` of `(element, force, preferences)`, one row per
+    /// line of the grid. Tiles without preferences stay put.
+    fn world(rows: &[&[Cell]]) -> World {
+        let config = Config::default();
+        let (width, height) = (rows[0].len(), rows.len());
+        let cell = |x: usize, y: usize| &rows[y][x];
+        let elements = Grid::new(width, height, |x, y| {
+            Tile::new(config.elements.lookup(cell(x, y).0).unwrap(), 0.5, 20.0)
+        });
+        let forces = Grid::new(width, height, |x, y| {
+            let (fx, fy) = cell(x, y).1;
+            Vector2::new(fx, fy)
+        });
+        let potential_moves = Grid::new(width, height, |x, y| {
+            let preferences = cell(x, y).2;
+            if preferences.is_empty() {
+                PotentialMoves::new(vec![(x as isize, y as isize)])
+            } else {
+                PotentialMoves::new(preferences.to_vec())
+            }
+        });
+        World {
+            config,
+            elements,
+            forces: ForceField::with_forces(forces),
+            potential_moves,
+        }
+    }
+
+    /// Asserts that every cell gets exactly one tile and that every tile ends up in exactly one
+    /// cell.
+    fn assert_permutation(sources: &Grid<Source>) {
+        let mut origins: Vec<_> = sources
+            .iter()
+            .map(|s| match *s {
+                Source::Tile(x, y) | Source::Path { tile: (x, y), .. } => (x, y),
+                Source::Exited { .. } => panic!("no tile should leave the world"),
+            })
+            .collect();
+        origins.sort_unstable();
+        origins.dedup();
+        assert_eq!(origins.len(), sources.width() * sources.height());
+    }
+
+    #[test]
+    fn swaps_need_opposing_forces_and_a_denser_tile_on_top() {
+        let mut world = world(&[
+            &[("soil", (0.0, 1.0), &[]), ("soil", (0.0, 3.0), &[])],
+            &[("air", (0.0, -1.0), &[]), ("air", (0.0, 1.0), &[])],
+            &[("air", (0.0, 0.0), &[]), ("air", (0.0, 0.0), &[])],
+        ]);
+        let (contest, _) = world.contest();
+
+        assert!(contest.favors_swap((0, 0), (0, 1)));
+        // Pushed harder than the air below, but the same way.
+        assert!(!contest.favors_swap((1, 0), (1, 1)));
+        // The lighter tile never pushes its way through the denser one.
+        assert!(!contest.favors_swap((0, 1), (0, 0)));
+    }
+
+    #[test]
+    fn heavy_tiles_swap_with_light_ones_before_other_moves() {
+        // The air at (1, 1) is pushed hardest toward (0, 0), and the air at (0, 1) harder than the
+        // soil, so the soil would stay put if the swap waited for the greedy resolver.
+        let mut world = world(&[
+            &[
+                ("soil", (0.0, 1.0), &[(0, 1), (0, 0)]),
+                ("soil", (0.0, 0.0), &[(1, 0)]),
+            ],
+            &[
+                ("air", (0.0, -2.0), &[(0, 0), (0, 1)]),
+                ("air", (-3.0, -3.0), &[(0, 0), (1, 1)]),
+            ],
+            &[("air", (0.0, 0.0), &[]), ("air", (0.0, 0.0), &[])],
+        ]);
+
+        let resolution = world.resolve(Resolver::Greedy, true);
+
+        let sources = &resolution.sources;
+        assert_permutation(sources);
+        assert_eq!(*sources.get(0, 0).unwrap(), Source::Tile(0, 1));
+        assert_eq!(*sources.get(0, 1).unwrap(), Source::Tile(0, 0));
+        assert_eq!(*sources.get(1, 1).unwrap(), Source::Tile(1, 1));
+        assert_eq!(*sources.get(0, 2).unwrap(), Source::Tile(0, 2));
+        assert_eq!(resolution.stats.unwrap().moved, 2);
+    }
+
+    #[test]
+    fn a_tile_left_without_a_cell_swaps_with_the_one_that_took_it() {
+        // The soil wins the air's cell, and the air has nowhere else to go.
+        let mut world = world(&[
+            &[("soil", (0.0, 2.0), &[(0, 1), (0, 0)])],
+            &[("air", (0.0, -1.0), &[(0, 1)])],
+            &[("air", (0.0, 0.0), &[])],
+        ]);
+        let order = world.order();
+        let (contest, potential_moves) = world.contest();
+        let mut conflicts = Grid::new(1, 3, |_, _| MoveConflict::new());
+
+        resolve_greedily(
+            &LargestForce,
+            &contest,
+            &mut conflicts,
+            potential_moves,
+            &order,
+            &mut rng(),
+        );
+
+        for (here, source) in [((0, 0), (0, 1)), ((0, 1), (0, 0))] {
+            let c = conflicts.get(here.0, here.1).unwrap();
+            assert!(c.is_resolved());
+            assert_eq!(c.resolved_move(), source);
+        }
+    }
+
+    #[test]
+    fn paths_stop_before_denser_tiles_and_push_the_tiles_passed_back() {
+        // Tall enough that the cells further along the path can't look like they lie above the
+        // soil the short way around.
+        let mut world = world(&[
+            &[("soil", (0.0, 10.0), &[(0, 1), (0, 0)])],
+            &[("air", (0.0, -1.0), &[])],
+            &[("air", (0.0, -1.0), &[])],
+            &[("soil", (0.0, -1.0), &[])],
+            &[STILL],
+            &[STILL],
+            &[STILL],
+            &[STILL],
+            &[STILL],
+        ]);
+        // The soil could sink all the way to the bottom, but the soil below the air is no lighter
+        // than it.
+        let path = (1..9).map(|y| (0, y)).collect();
+        let p = world.potential_moves.get_mut(0, 0).unwrap();
+        *p = p.clone().with_path(path);
+
+        let resolution = world.resolve(Resolver::Greedy, true);
+
+        assert_permutation(&resolution.sources);
+        // The soil lands in the last air cell, and each air tile it passed moved up one cell.
+        let sources: Vec<_> = resolution.sources.iter().copied().take(4).collect();
+        let path = Source::Path {
+            tile: (0, 0),
+            step: (0, 1),
+        };
+        assert_eq!(
+            sources,
+            [
+                Source::Tile(0, 1),
+                Source::Tile(0, 2),
+                path,
+                Source::Tile(0, 3)
+            ]
+        );
+        let stats = resolution.stats.unwrap();
+        assert_eq!(stats.moved, 3);
+        // Two air tiles moved up and the soil moved down.
+        assert_eq!(stats.directions[6], 2);
+        assert_eq!(stats.directions[2], 1);
+        // Every tile claimed only the cell it ended up in.
+        let claimants = stats.claimants;
+        assert!(claimants.iter().all(|&c| c == 1), "{claimants:?}");
+    }
+
+    #[test]
+    fn the_auction_lines_moves_up_with_forces_at_least_as_well_as_the_greedy_resolver() {
+        let resolve = |resolver| {
+            let mut world = world(CONTESTED);
+            let resolution = world.resolve(resolver, true);
+            assert_permutation(&resolution.sources);
+            let (contest, _) = world.contest();
+            (alignment(&contest, &resolution.sources), resolution)
+        };
+
+        let (greedy, greedy_resolution) = resolve(Resolver::Greedy);
+        let (auction, auction_resolution) = resolve(Resolver::Auction);
+        assert!(auction >= greedy, "{auction} < {greedy}");
+        assert!((auction - 3.0).abs() < 0.01, "{auction}");
+        assert_eq!(greedy_resolution.bids, 0);
+        assert_eq!(auction_resolution.iterations, 1);
+        assert!(auction_resolution.bids > 0);
+    }
+
+    #[test]
+    fn claimants_include_claims_from_later_rounds() {
+        let resolution = world(CONTESTED).resolve(Resolver::Greedy, true);
+
+        let claimants = resolution.stats.unwrap().claimants;
+        // The tile at (1, 1) only tries (0, 1) once it has lost (2, 1).
+        let middle_row: Vec<_> = (0..3).map(|x| *claimants.get(x, 1).unwrap()).collect();
+        assert_eq!(middle_row, [1, 2, 2]);
+        assert_eq!(*claimants.get(1, 0).unwrap(), 1);
+    }
+
+    #[test]
+    fn stats_are_only_gathered_when_asked_for() {
+        let resolution = world(&[&[STILL], &[STILL], &[STILL]]).resolve(Resolver::Greedy, false);
+
+        assert!(resolution.stats.is_none());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+
+use crate::grid::{Grid, GridLike};
+
+use super::{Contest, MoveConflict, PotentialMoves};
+
+/// How much more than the bare minimum every bid raises a price by, as a fraction of the most any
+/// tile values a move. The auction ends within this much of that value per tile of the best
+/// possible assignment, however strong the forces are.
+const EPSILON: f64 = 1e-3;
+
+/// Assigns every tile that isn't locked in place yet to one of the cells it could move to, so that
+/// the moves line up with the tiles' forces as well as possible in total, and resolves `conflicts`
+/// to match. Returns how many bids it took, counting every bid as a claim on its cell.
+///
+/// Tiles bid for cells in an auction: each in turn takes the cell worth the most to it at current
+/// prices, outbidding whoever held it by how much more that cell is worth to it than its second
+/// choice. Outbid tiles bid again until every tile holds a cell.
+///
+/// A tile that can leave the world may do so, leaving its cell to be filled from the boundary.
+/// This is modeled by giving it an exit of its own to bid on, and giving its cell a stand-in
+/// bidder that takes either the cell, when the tile leaves, or the exit, when it doesn't.
+pub(super) fn assign(
+    contest: &Contest,
+    conflicts: &mut Grid<MoveConflict>,
+    potential_moves: &mut Grid<PotentialMoves>,
+) -> usize {
+    let width = conflicts.width();
+    let n = width * conflicts.height();
+    let index = |x: isize, y: isize| y as usize * width + x as usize;
+    let coords = |i: usize| ((i % width) as isize, (i / width) as isize);
+
+    // Bidders are tiles, `0..n`, followed by the stand-ins for their cells, `n..2n`. Objects are
+    // cells, `0..n`, followed by the tiles' exits, `n..2n`.
+    // Tiles already locked into a cell, by a path or a swap, count as claims on it.
+    let mut placed = vec![false; n];
+    for (_, _, c) in conflicts.enumerate_mut() {
+        if c.is_locked() {
+            let (x, y) = c.resolved_move();
+            placed[index(x, y)] = true;
+            c.claims += 1;
+        }
+    }
+    let mut exits = vec![None; n];
+    let mut edges: Vec<Vec<(usize, f64)>> = vec![vec![]; 2 * n];
+    for (x, y, p) in potential_moves.enumerate() {
+        let (x, y) = (x as isize, y as isize);
+        let i = index(x, y);
+        if placed[i] {
+            continue;
+        }
+        for &(tx, ty) in p.preferences.iter() {
+            let value = if (tx, ty) == (x, y) {
+                0.0
+            } else {
+                contest
+                    .force(x, y)
+                    .dot(&contest.direction((x, y), (tx, ty))) as f64
+            };
+            match conflicts.get(tx, ty) {
+                Some(c) if !c.is_locked() => edges[i].push((index(tx, ty), value)),
+                Some(_) => {}
+                None if exits[i].is_none() => {
+                    exits[i] = Some((tx, ty));
+                    edges[i].push((n + i, value));
+                }
+                None => {}
+            }
+        }
+        if exits[i].is_some() {
+            edges[n + i] = vec![(i, 0.0), (n + i, 0.0)];
+        }
+    }
+
+    let largest = edges
+        .iter()
+        .flatten()
+        .fold(0.0, |max: f64, &(_, value)| max.max(value.abs()));
+    let epsilon = EPSILON * if largest > 0.0 { largest } else { 1.0 };
+
+    let mut prices = vec![0.0; 2 * n];
+    let mut owners: Vec<Option<usize>> = vec![None; 2 * n];
+    let mut unassigned: VecDeque<_> = (0..2 * n).filter(|&i| !edges[i].is_empty()).collect();
+    let mut bids = 0;
+    while let Some(bidder) = unassigned.pop_front() {
+        bids += 1;
+        let (mut best, mut best_value, mut second_value) =
+            (0, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &(object, value) in edges[bidder].iter() {
+            let net = value - prices[object];
+            if net > best_value {
+                (best, best_value, second_value) = (object, net, best_value);
+            } else if net > second_value {
+                second_value = net;
+            }
+        }
+        if best < n {
+            let (x, y) = coords(best);
+            conflicts.get_mut(x, y).unwrap().claims += 1;
+        }
+        prices[best] += if second_value.is_finite() {
+            best_value - second_value + epsilon
+        } else {
+            epsilon
+        };
+        if let Some(outbid) = owners[best].replace(bidder) {
+            unassigned.push_back(outbid);
+        }
+    }
+
+    for (cell, owner) in owners[..n].iter().enumerate() {
+        let (x, y) = coords(cell);
+        match *owner {
+            Some(tile) if tile < n => {
+                conflicts.get_mut(x, y).unwrap().resolve(coords(tile));
+                let (tx, ty) = coords(tile);
+                *potential_moves.get_mut(tx, ty).unwrap() = PotentialMoves::new(vec![(x, y)]);
+            }
+            // The cell's stand-in took it, so its tile left through its exit.
+            Some(_) => {
+                *potential_moves.get_mut(x, y).unwrap() =
+                    PotentialMoves::new(vec![exits[cell].unwrap()]);
+            }
+            None => {}
+        }
+    }
+
+    bids
+}
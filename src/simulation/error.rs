@@ -0,0 +1,72 @@
+use std::{error::Error, fmt};
+
+/// Something that went wrong during an update, leaving the state as it was partway through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationError {
+    /// Move resolution settled with nothing moving into the cell at `(x, y)`, even though the tile
+    /// there was leaving it.
+    UnresolvedConflict {
+        x: isize,
+        y: isize,
+        /// Every tile that listed the cell among the moves it would make.
+        candidates: Vec<(isize, isize)>,
+        /// How many rounds of resolution ran before giving up.
+        iterations: usize,
+    },
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::UnresolvedConflict {
+                x,
+                y,
+                candidates,
+                iterations,
+            } => write!(
+                f,
+                "Unresolved conflict at ({x}, {y}) after {iterations} iterations, \
+                 candidates {candidates:?}"
+            ),
+        }
+    }
+}
+
+impl Error for SimulationError {}
+
+/// A config that can't be simulated, caught when it is built or loaded rather than partway
+/// through an update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// More element definitions than an [`Element`](super::Element) can index.
+    TooManyElements(usize),
+    /// Part of the config, described by `context`, names an element that isn't defined.
+    UnknownElement { name: String, context: String },
+    /// The neighborhood has an offset without its opposite, so tiles would exchange moisture and
+    /// heat with neighbors that don't exchange back.
+    AsymmetricNeighborhood,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::TooManyElements(count) => {
+                write!(
+                    f,
+                    "{count} element definitions, but at most 256 are supported"
+                )
+            }
+            ConfigError::UnknownElement { name, context } => {
+                write!(f, "{context} refers to unknown element {name:?}")
+            }
+            ConfigError::AsymmetricNeighborhood => {
+                write!(
+                    f,
+                    "The neighborhood should contain the opposite of every offset"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
@@ -1,21 +1,38 @@
+mod pressure;
+
 use std::cmp::Reverse;
 
 use image::{Rgb, RgbImage};
 use nalgebra::Vector2;
 use ordered_float::OrderedFloat;
 use palette::{FromColor, Srgb};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    grid::{ArrayGrid, Grid, GridEnumerator, GridLike},
+    grid::{
+        chunked::ChunkedGrid,
+        topology::{Offset, Stencil},
+        Grid, GridLike,
+    },
     pageflip::PageFlip,
 };
 
-use super::{config::Config, conflict::PotentialMoves, Tile};
+use self::pressure::PressureSystem;
+
+use super::{
+    boundary::{Boundaries, Boundary, Neighbor},
+    config::{Config, Gravity, Movement, PressureMethod},
+    conflict::PotentialMoves,
+    Tile,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForceField {
     forces: PageFlip<Grid<Vector2<f32>>>,
     pressures: PageFlip<Grid<f32>>,
+    /// The pressure equations for the tiles of the current update, when pressure is solved for.
+    #[serde(skip)]
+    pressure_system: Option<PressureSystem>,
 }
 
 impl ForceField {
@@ -23,102 +40,232 @@ impl ForceField {
         Self {
             forces: PageFlip::new(|| Grid::new(width, height, |_, _| Vector2::new(0.0, 0.0))),
             pressures: PageFlip::new(|| Grid::new(width, height, |_, _| 0.0)),
+            pressure_system: None,
         }
     }
 
-    pub fn init(&mut self, config: &Config, elements: &Grid<Tile>) {
-        for (x, y) in GridEnumerator::new(elements) {
-            *self.forces.write().get_mut(x as isize, y as isize).unwrap() =
-                cumulative_attractive_forces_on_xy(x as isize, y as isize, elements, config);
+    /// A field holding `forces`, with no pressure.
+    #[cfg(test)]
+    pub(crate) fn with_forces(forces: Grid<Vector2<f32>>) -> Self {
+        let (width, height) = (forces.width(), forces.height());
+        Self {
+            forces: PageFlip::new(|| forces.clone()),
+            pressures: PageFlip::new(|| Grid::new(width, height, |_, _| 0.0)),
+            pressure_system: None,
         }
+    }
+
+    /// Resets the force on every active tile to gravity plus attraction, and pressure to zero, and
+    /// sets up the pressure equations for the tiles as they are. Tiles in sleeping chunks keep
+    /// the force they settled with.
+    pub fn init(&mut self, config: &Config, elements: &ChunkedGrid<Tile>) {
+        let attraction = Attraction::new(config, elements);
+        let (width, height) = (elements.width(), elements.height());
+        let forces = self.forces.read();
+        let new_forces = Grid::par_new(width, height, |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            if !elements.is_active(x, y) {
+                return *forces.get(x, y).unwrap();
+            }
+            gravity_on_xy(x as usize, y as usize, width, height, &config.gravity)
+                + cumulative_attractive_forces_on_xy(
+                    x,
+                    y,
+                    elements,
+                    &attraction,
+                    &config.boundaries,
+                )
+        });
+        *self.forces.write() = new_forces;
         self.forces.flip();
+        *self.pressures.write() = Grid::new(width, height, |_, _| 0.0);
+        self.pressures.flip();
+        self.pressure_system = (config.pressure.method != PressureMethod::Heuristic)
+            .then(|| PressureSystem::new(config, elements));
     }
 
-    pub fn update(&mut self, elements: &Grid<Tile>, config: &Config) {
+    /// Runs one relaxation iteration, returning the largest change in any tile's force. Every
+    /// stage computes each cell independently from the previous stage, so the result does not
+    /// depend on how many threads run it. Pressure is computed everywhere, but only active tiles
+    /// have their forces updated.
+    pub fn update(&mut self, elements: &ChunkedGrid<Tile>, config: &Config) -> f32 {
+        let (width, height) = (elements.width(), elements.height());
+        let stencil = config.stencil();
+        // The force each neighbor pushes onto each tile, in the order of the stencil.
         let other_force_on = {
             let forces = self.forces.read();
-            Grid::new(forces.width(), forces.height(), |x, y| {
+            Grid::par_new(width, height, |x, y| {
                 let (x, y) = (x as isize, y as isize);
                 let t = elements.get(x, y).unwrap();
                 let f = *forces.get(x, y).unwrap();
-                ArrayGrid::<Vector2<f32>, 3, 3>::new(|dx, dy| {
-                    let (dx, dy) = (dx as isize - 1, dy as isize - 1);
-                    if dx != 0 || dy != 0 {
-                        let (ox, oy) = (x + dx, y + dy);
-                        let of = forces
-                            .get(ox, oy)
-                            .map(|of| {
+                stencil
+                    .offsets(y)
+                    .iter()
+                    .map(|o| {
+                        let of = match config.boundaries.resolve(
+                            x + o.dx,
+                            y + o.dy,
+                            forces.width(),
+                            forces.height(),
+                        ) {
+                            Neighbor::Inside(ox, oy) => {
                                 let o = elements.get(ox, oy).unwrap();
-                                of * o.density(config) / t.density(config)
-                            })
-                            .unwrap_or_else(|| -f);
-                        let proj_of = project_incoming_force_onto_cell(dx, dy, &of);
-                        proj_of
-                    } else {
-                        Vector2::zeros()
-                    }
-                })
+                                forces.get(ox, oy).unwrap() * o.density(config) / t.density(config)
+                            }
+                            Neighbor::Outside(edge) if config.boundaries.get(edge).is_solid() => -f,
+                            Neighbor::Outside(_) => Vector2::zeros(),
+                        };
+                        project_incoming_force_onto_cell(&o.displacement, &of)
+                    })
+                    .collect::<Vec<_>>()
             })
         };
 
-        for (x, y) in GridEnumerator::new(self.forces.read()) {
-            let (x, y) = (x as isize, y as isize);
-            *self.pressures.write().get_mut(x, y).unwrap() = pressure_on_xy(x, y, &other_force_on);
-        }
+        // A real pressure solve projects out the divergence of the forces each tile would end up
+        // with before pressure. Pressure builds up over the iterations, one correction at a time.
+        let projection = match config.pressure.method {
+            PressureMethod::Heuristic => {
+                *self.pressures.write() = other_force_on.par_map(|ofs| pressure_on_xy(ofs));
+                None
+            }
+            _ => {
+                let forces = self.forces.read();
+                let provisional = Grid::par_new(width, height, |x, y| {
+                    let (x, y) = (x as isize, y as isize);
+                    forces.get(x, y).unwrap()
+                        + other_force_on
+                            .get(x, y)
+                            .unwrap()
+                            .iter()
+                            .sum::<Vector2<f32>>()
+                });
+                let projection = self
+                    .pressure_system
+                    .get_or_insert_with(|| PressureSystem::new(config, elements))
+                    .solve(config, provisional);
+                *self.pressures.write() = self
+                    .pressures
+                    .read()
+                    .zip_with(&projection.pressures, |old, new| old + new);
+                Some(projection)
+            }
+        };
         self.pressures.flip();
 
-        for (x, y) in GridEnumerator::new(self.forces.read()) {
-            let (x, y) = (x as isize, y as isize);
-            *self.forces.write().get_mut(x as isize, y as isize).unwrap() = new_force_for_xy(
-                x,
-                y,
-                self.forces.read(),
-                self.pressures.read(),
-                &other_force_on,
-            );
-        }
+        let new_forces = {
+            let (forces, pressures) = (self.forces.read(), self.pressures.read());
+            Grid::par_new(width, height, |x, y| {
+                let (x, y) = (x as isize, y as isize);
+                if !elements.is_active(x, y) {
+                    return *forces.get(x, y).unwrap();
+                }
+                let pressure = match &projection {
+                    Some(projection) => {
+                        Pressure::Solved(projection.push_on_xy(x, y, config, elements))
+                    }
+                    None => Pressure::Heuristic(pressures),
+                };
+                new_force_for_xy(
+                    x,
+                    y,
+                    forces,
+                    pressure,
+                    &other_force_on,
+                    stencil.with_center(y),
+                    &config.boundaries,
+                )
+            })
+        };
+        let residual = new_forces
+            .zip_with(self.forces.read(), |new, old| (new - old).norm())
+            .iter()
+            .copied()
+            .fold(0.0, f32::max);
+        *self.forces.write() = new_forces;
         self.forces.flip();
+
+        residual
+    }
+
+    /// Minimum, maximum and mean pressure from the last update.
+    pub fn pressure_stats(&self) -> (f32, f32, f32) {
+        let pressures = self.pressures.read();
+        let (min, max, total) = pressures.iter().fold(
+            (f32::INFINITY, f32::NEG_INFINITY, 0.0),
+            |(min, max, total), p| (min.min(*p), max.max(*p), total + p),
+        );
+        let count = (pressures.width() * pressures.height()).max(1);
+        (min, max, total / count as f32)
     }
 
     pub fn get(&self, x: isize, y: isize) -> Option<&Vector2<f32>> {
         self.forces.read().get(x, y)
     }
 
-    pub fn potential_moves(&self) -> Grid<PotentialMoves> {
-        Grid::new(
-            self.forces.read().width(),
-            self.forces.read().height(),
-            |x, y| {
-                let mut moves: Vec<_> = (-1..=1)
-                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
-                    .filter(|(dx, dy)| {
-                        let x = x as isize + dx;
-                        let y = y as isize + dy;
-                        !(x < 0
-                            || y < 0
-                            || x as usize >= self.forces.read().width()
-                            || y as usize >= self.forces.read().height())
-                    })
-                    .collect();
-
-                let f = self.forces.read().get(x as isize, y as isize).unwrap();
-                moves.sort_unstable_by_key(|(dx, dy)| {
-                    Reverse(OrderedFloat(
-                        Vector2::new(*dx as f32, *dy as f32)
-                            .try_normalize(f32::EPSILON)
-                            .unwrap_or(Vector2::zeros())
-                            .dot(f),
-                    ))
-                });
+    /// Whether every force and pressure buffer covers a `width` x `height` world.
+    pub fn has_size(&self, width: usize, height: usize) -> bool {
+        let forces = self.forces.pages().map(|g| (g.width(), g.height()));
+        let pressures = self.pressures.pages().map(|g| (g.width(), g.height()));
+        forces
+            .into_iter()
+            .chain(pressures)
+            .all(|size| size == (width, height))
+    }
 
-                PotentialMoves::new(
-                    moves
-                        .into_iter()
-                        .map(|(dx, dy)| (x as isize + dx, y as isize + dy))
-                        .collect(),
-                )
-            },
-        )
+    /// Every cell in its neighborhood each tile could move to, most preferred first, including
+    /// staying put. Moves through open boundaries target coordinates outside the grid. Tiles in
+    /// sleeping chunks stay put, and no tile moves into them.
+    pub fn potential_moves(
+        &self,
+        elements: &ChunkedGrid<Tile>,
+        boundaries: &Boundaries,
+        movement: &Movement,
+        stencil: &Stencil,
+    ) -> Grid<PotentialMoves> {
+        let (width, height) = (self.forces.read().width(), self.forces.read().height());
+        Grid::par_new(width, height, |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            if !elements.is_active(x, y) {
+                return PotentialMoves::new(vec![(x, y)]);
+            }
+            let mut moves: Vec<_> = stencil
+                .with_center(y)
+                .iter()
+                .filter_map(|o| {
+                    let (tx, ty) = (x + o.dx, y + o.dy);
+                    match boundaries.resolve(tx, ty, width, height) {
+                        Neighbor::Inside(tx, ty) if elements.is_active(tx, ty) => {
+                            Some((o.displacement, (tx, ty)))
+                        }
+                        Neighbor::Inside(..) => None,
+                        Neighbor::Outside(edge) => match boundaries.get(edge) {
+                            Boundary::Open(_) => Some((o.displacement, (tx, ty))),
+                            _ => None,
+                        },
+                    }
+                })
+                .collect();
+
+            let f = self.forces.read().get(x, y).unwrap();
+            moves.sort_unstable_by_key(|(displacement, _)| {
+                Reverse(OrderedFloat(
+                    displacement
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or(Vector2::zeros())
+                        .dot(f),
+                ))
+            });
+
+            let budget = movement.budget(f);
+            let path = if budget > 1 {
+                path(x, y, f, budget, elements, boundaries, stencil)
+            } else {
+                vec![]
+            };
+
+            PotentialMoves::new(moves.into_iter().map(|(_, target)| target).collect())
+                .with_path(path)
+        })
     }
 
     pub fn force_image(&self) -> RgbImage {
@@ -191,12 +338,22 @@ impl ForceField {
     }
 }
 
+/// How pressure pushes on a tile.
+enum Pressure<'a> {
+    /// Toward the neighbor with the least pressure, by how much less it has.
+    Heuristic(&'a Grid<f32>),
+    /// By this much, from a solved pressure's gradient.
+    Solved(Vector2<f32>),
+}
+
 fn new_force_for_xy(
     x: isize,
     y: isize,
     forces: &Grid<Vector2<f32>>,
-    pressures: &Grid<f32>,
-    other_force_on: &Grid<ArrayGrid<Vector2<f32>, 3, 3>>,
+    pressure: Pressure,
+    other_force_on: &Grid<Vec<Vector2<f32>>>,
+    with_center: &[Offset],
+    boundaries: &Boundaries,
 ) -> Vector2<f32> {
     let mut f = *forces.get(x, y).unwrap();
 
@@ -205,30 +362,37 @@ fn new_force_for_xy(
         f += of;
     }
 
-    let (dx, dy, &op) = pressures
-        .window_at(3, (x as usize, y as usize))
-        .enumerate()
-        .min_by_key(|(_dx, _dy, op)| OrderedFloat(**op))
+    let pressures = match pressure {
+        Pressure::Heuristic(pressures) => pressures,
+        Pressure::Solved(push) => return f + push,
+    };
+
+    let (toward, op) = with_center
+        .iter()
+        .filter_map(|o| {
+            match boundaries.resolve(x + o.dx, y + o.dy, pressures.width(), pressures.height()) {
+                Neighbor::Inside(ox, oy) => Some((o.displacement, *pressures.get(ox, oy).unwrap())),
+                Neighbor::Outside(_) => None,
+            }
+        })
+        .min_by_key(|(_toward, op)| OrderedFloat(*op))
         .unwrap();
     let p = *pressures.get(x, y).unwrap();
 
     let p_diff = (p - op);
     if p_diff > 0.0 {
         f += p_diff
-            * Vector2::new(dx as f32, dy as f32)
+            * toward
                 .try_normalize(f32::EPSILON)
                 .unwrap_or(Vector2::zeros());
     }
 
-    println!("{y}: {f}");
-
     f
 }
 
-fn pressure_on_xy(x: isize, y: isize, other_force_on: &Grid<ArrayGrid<Vector2<f32>, 3, 3>>) -> f32 {
+fn pressure_on_xy(other_forces_on_xy: &[Vector2<f32>]) -> f32 {
     let mut pressure = 0.0;
 
-    let other_forces_on_xy = other_force_on.get(x, y).unwrap();
     for (i, of1) in other_forces_on_xy.iter().enumerate() {
         for of2 in other_forces_on_xy.iter().skip(i + 1) {
             let opposition = of1.dot(of2);
@@ -241,42 +405,105 @@ fn pressure_on_xy(x: isize, y: isize, other_force_on: &Grid<ArrayGrid<Vector2<f3
     pressure
 }
 
-fn cumulative_attractive_forces_on_xy(
-    x: isize,
-    y: isize,
-    elements: &Grid<Tile>,
-    config: &Config,
-) -> Vector2<f32> {
-    let mut force = 10.0f32 * Vector2::y();
-    // for d in 1..=2 {
-    //     for i in -d..d {
-    //         let edges = [(i, -d), (d, i), (d - i, d), (-d, d - i)];
-    //         for (dx, dy) in edges {
-    //             force += attractive_force_on_xy(x, y, dx, dy, elements, config);
-    //         }
-    //     }
-    // }
-    force
+fn gravity_on_xy(x: usize, y: usize, width: usize, height: usize, gravity: &Gravity) -> Vector2<f32> {
+    let (fx, fy) = (
+        (x as f32 + 0.5) / width as f32,
+        (y as f32 + 0.5) / height as f32,
+    );
+    let direction_of = |degrees: f32| {
+        let radians = degrees.to_radians();
+        Vector2::new(radians.cos(), radians.sin())
+    };
+
+    if let Some(region) = gravity.regions.0.iter().rev().find(|r| r.contains(fx, fy)) {
+        return region.magnitude.as_f32() * direction_of(region.direction.as_f32());
+    }
+
+    let uniform = direction_of(gravity.direction.as_f32());
+    let radial = Vector2::new(
+        (gravity.center_x.as_f32() - fx) * width as f32,
+        (gravity.center_y.as_f32() - fy) * height as f32,
+    )
+    .try_normalize(f32::EPSILON)
+    .unwrap_or(Vector2::zeros());
+    let blend = gravity.radial.as_f32();
+
+    gravity.magnitude.as_f32() * ((1.0 - blend) * uniform + blend * radial)
+}
+
+/// Everything needed to compute attraction between tiles, evaluated once per [`ForceField::init`]
+/// so the per-tile work is a walk over a fixed stencil.
+struct Attraction {
+    cohesion: Grid<f32>,
+    adhesion: Grid<f32>,
+    /// Offsets within the interaction radius, each paired with the unit vector toward it scaled
+    /// by the distance falloff, for tiles in even and odd rows.
+    stencil: [Vec<(isize, isize, Vector2<f32>)>; 2],
+}
+
+impl Attraction {
+    fn new(config: &Config, elements: &Grid<Tile>) -> Self {
+        let radius = config.attraction.radius.as_f32();
+        let falloff = config.attraction.falloff.as_f32();
+        // Rows are never further apart than columns, so this covers the radius on any topology.
+        let r = radius.ceil() as isize + 1;
+
+        Self {
+            cohesion: elements.par_map(|t| t.cohesion(config)),
+            adhesion: elements.par_map(|t| t.adhesion(config)),
+            stencil: [0, 1].map(|y| {
+                (-r..=r)
+                    .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+                    .filter(|(dx, dy)| *dx != 0 || *dy != 0)
+                    .map(|(dx, dy)| (dx, dy, config.topology.displacement(dx, dy, y)))
+                    .filter(|(_, _, d)| d.norm() <= radius)
+                    .map(|(dx, dy, d)| {
+                        let distance = d.norm();
+                        (dx, dy, d / distance.powf(1.0 + falloff))
+                    })
+                    .collect()
+            }),
+        }
+    }
 }
 
-fn attractive_force_on_xy(
+fn cumulative_attractive_forces_on_xy(
     x: isize,
     y: isize,
-    dx: isize,
-    dy: isize,
     elements: &Grid<Tile>,
-    config: &Config,
+    attraction: &Attraction,
+    boundaries: &Boundaries,
 ) -> Vector2<f32> {
+    let mut force = Vector2::zeros();
+
     let t = elements.get(x, y).unwrap();
-    let d = Vector2::new(dx as f32, dy as f32).normalize();
-    if let Some(ot) = elements.get(x + dx, y + dy) {
-        return t.attractive_force(ot, config) * d;
+    let cohesion = *attraction.cohesion.get(x, y).unwrap();
+    let adhesion = *attraction.adhesion.get(x, y).unwrap();
+    for (dx, dy, d) in attraction.stencil[y.rem_euclid(2) as usize].iter() {
+        let Neighbor::Inside(ox, oy) =
+            boundaries.resolve(x + dx, y + dy, elements.width(), elements.height())
+        else {
+            continue;
+        };
+        if let Some(ot) = elements.get(ox, oy) {
+            let strength = if t.element() == ot.element() {
+                cohesion * attraction.cohesion.get(ox, oy).unwrap()
+            } else {
+                adhesion * attraction.adhesion.get(ox, oy).unwrap()
+            };
+            force += strength * *d;
+        }
     }
-    Vector2::zeros()
+
+    force
 }
 
-fn project_incoming_force_onto_cell(dx: isize, dy: isize, of: &Vector2<f32>) -> Vector2<f32> {
-    let d = -Vector2::new(dx as f32, dy as f32).normalize();
+/// The part of the force `of` pushing back toward a cell from its neighbor at `displacement`.
+fn project_incoming_force_onto_cell(
+    displacement: &Vector2<f32>,
+    of: &Vector2<f32>,
+) -> Vector2<f32> {
+    let d = -displacement.normalize();
     let scale = (of).dot(&d);
     if scale < 0.0 {
         scale * d
@@ -284,3 +511,100 @@ fn project_incoming_force_onto_cell(dx: isize, dy: isize, of: &Vector2<f32>) ->
         Vector2::zeros()
     }
 }
+
+/// The cells a tile at `(x, y)` pushed by `f` passes through on its way to traveling `budget`
+/// steps, each to a neighbor, as straight along `f` as the stencil allows. The path stops early
+/// at the first cell it can't enter: a wall, the edge of the world, a sleeping cell or a cell it
+/// already passed through.
+fn path(
+    x: isize,
+    y: isize,
+    f: &Vector2<f32>,
+    budget: usize,
+    elements: &ChunkedGrid<Tile>,
+    boundaries: &Boundaries,
+    stencil: &Stencil,
+) -> Vec<(isize, isize)> {
+    let Some(direction) = f.try_normalize(f32::EPSILON) else {
+        return vec![];
+    };
+    let (width, height) = (elements.width(), elements.height());
+    let mut path = vec![];
+    let (mut cx, mut cy) = (x, y);
+    let mut traveled = Vector2::zeros();
+    for _ in 0..budget {
+        // The step forward that stays closest to the straight line along the force.
+        let Some(step) = stencil
+            .offsets(cy)
+            .iter()
+            .filter(|o| o.displacement.dot(&direction) > 0.0)
+            .min_by_key(|o| {
+                let p = traveled + o.displacement;
+                OrderedFloat((p - direction * p.dot(&direction)).norm())
+            })
+        else {
+            break;
+        };
+        match boundaries.resolve(cx + step.dx, cy + step.dy, width, height) {
+            Neighbor::Inside(tx, ty)
+                if elements.is_active(tx, ty)
+                    && (tx, ty) != (x, y)
+                    && !path.contains(&(tx, ty)) =>
+            {
+                path.push((tx, ty));
+                (cx, cy) = (tx, ty);
+                traveled += step.displacement;
+            }
+            _ => break,
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clamped_f32::ClampedF32, simulation::boundary::BoundaryTile};
+
+    /// The path of a tile at `(1, 1)` of a 3x8 world of air pushed straight down, hard enough to
+    /// travel the whole height of the world.
+    fn path_down(boundaries: &Boundaries) -> Vec<(isize, isize)> {
+        let config = Config::default();
+        let air = config.elements.lookup("air").unwrap();
+        let elements = ChunkedGrid::new(Grid::new(3, 8, |_, _| Tile::new(air, 0.5, 20.0)), 8);
+        let f = Vector2::new(0.0, 100.0);
+        path(1, 1, &f, 10, &elements, boundaries, &config.stencil())
+    }
+
+    #[test]
+    fn paths_stop_at_walls() {
+        let path = path_down(&Boundaries::default());
+        assert_eq!(path, (2..8).map(|y| (1, y)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn paths_stop_at_open_edges() {
+        let air = BoundaryTile {
+            element: "air".to_string(),
+            saturation: ClampedF32::new(0.5),
+            temperature: ClampedF32::new(20.0),
+        };
+        let boundaries = Boundaries {
+            bottom: Boundary::Open(air),
+            ..Boundaries::default()
+        };
+        let path = path_down(&boundaries);
+        assert_eq!(path, (2..8).map(|y| (1, y)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn paths_wrap_around_periodic_edges_until_they_come_back() {
+        let boundaries = Boundaries {
+            top: Boundary::Periodic,
+            bottom: Boundary::Periodic,
+            ..Boundaries::default()
+        };
+        let path = path_down(&boundaries);
+        assert_eq!(path, [2, 3, 4, 5, 6, 7, 0].map(|y| (1, y)));
+    }
+}
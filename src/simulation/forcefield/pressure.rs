@@ -0,0 +1,439 @@
+use nalgebra::Vector2;
+
+use crate::grid::{
+    chunked::ChunkedGrid, neighborhood::Neighborhood, topology::Stencil, Grid, GridLike,
+};
+
+use crate::simulation::{
+    boundary::Neighbor,
+    config::{Config, PressureMethod},
+    Tile,
+};
+
+/// Densities are clamped to at least this much, since a tile that weighs nothing would be pushed
+/// infinitely hard.
+const MIN_DENSITY: f32 = 0.01;
+
+/// Relaxation sweeps before and after every coarse grid correction in a multigrid V-cycle.
+const SMOOTHING_SWEEPS: usize = 2;
+
+/// Sweeps on the coarsest grid of a V-cycle, which is small enough for these to be cheap.
+const COARSEST_SWEEPS: usize = 32;
+
+/// How far a Jacobi sweep moves every cell toward the pressure that satisfies its equation. Going
+/// all the way leaves pressure oscillating between neighboring cells, which never dies down on a
+/// world closed off by walls.
+const JACOBI_WEIGHT: f32 = 0.8;
+
+/// The pressure `p` that makes the forces on the tiles, less the pressure gradient divided by each
+/// tile's density, free of divergence, so that tiles neither bunch up nor spread out:
+///
+/// ```text
+/// ∇·(∇p / ρ) = ∇·f
+/// ```
+///
+/// Both sides are discretized over the nearest neighbors of every cell, whatever the configured
+/// neighborhood. Solid walls keep pressure from leaking through them and push back on forces into
+/// them, and open boundaries hold the pressure just outside at zero.
+pub struct Projection {
+    stencil: Stencil,
+    forces: Grid<Vector2<f32>>,
+    pub pressures: Grid<f32>,
+}
+
+/// The left-hand side of the pressure equation for the tiles as they are. It only depends on the
+/// tiles' densities and the boundaries, so one system serves every relaxation iteration of an
+/// update.
+#[derive(Debug, Clone)]
+pub struct PressureSystem {
+    stencil: Stencil,
+    /// The equations on the grid itself, followed, for multigrid, by ever coarser ones.
+    levels: Vec<Level>,
+}
+
+impl PressureSystem {
+    pub fn new(config: &Config, elements: &ChunkedGrid<Tile>) -> Self {
+        let stencil = config
+            .topology
+            .stencil(&Neighborhood::VonNeumann { radius: 1 });
+        let (width, height) = (elements.width(), elements.height());
+        let density =
+            |x: isize, y: isize| elements.get(x, y).unwrap().density(config).max(MIN_DENSITY);
+
+        let level = Level {
+            links: Grid::par_new(width, height, |x, y| {
+                let (x, y) = (x as isize, y as isize);
+                stencil
+                    .offsets(y)
+                    .iter()
+                    .filter_map(|o| {
+                        match config.boundaries.resolve(x + o.dx, y + o.dy, width, height) {
+                            Neighbor::Inside(nx, ny) => Some(Link {
+                                to: Some((nx, ny)),
+                                weight: 0.5 * (1.0 / density(x, y) + 1.0 / density(nx, ny)),
+                            }),
+                            Neighbor::Outside(edge) if config.boundaries.get(edge).is_solid() => {
+                                None
+                            }
+                            Neighbor::Outside(_) => Some(Link {
+                                to: None,
+                                weight: 1.0 / density(x, y),
+                            }),
+                        }
+                    })
+                    .collect()
+            }),
+        };
+        let levels = match config.pressure.method {
+            PressureMethod::Multigrid => level.hierarchy(),
+            _ => vec![level],
+        };
+
+        Self { stencil, levels }
+    }
+
+    /// Solves for the pressure that makes `forces` free of divergence with `config.pressure`.
+    pub fn solve(&self, config: &Config, forces: Grid<Vector2<f32>>) -> Projection {
+        let divergence = self.divergence(config, &forces);
+        let finest = &self.levels[0];
+        let solver = &config.pressure;
+        let target =
+            solver.tolerance.as_f32() * divergence.iter().fold(0.0, |max: f32, d| max.max(d.abs()));
+        let mut pressures = Grid::new(divergence.width(), divergence.height(), |_, _| 0.0);
+        for _ in 0..solver.max_iterations {
+            if finest.largest_residual(&pressures, &divergence) <= target {
+                break;
+            }
+            pressures = match solver.method {
+                PressureMethod::Jacobi => finest.jacobi(&pressures, &divergence),
+                PressureMethod::RedBlackGaussSeidel => finest.red_black(&pressures, &divergence),
+                PressureMethod::Multigrid => v_cycle(&self.levels, pressures, &divergence),
+                PressureMethod::Heuristic => unreachable!("Heuristic pressure is not solved for"),
+            };
+        }
+
+        Projection {
+            stencil: self.stencil.clone(),
+            forces,
+            pressures,
+        }
+    }
+
+    /// The right-hand side of the pressure equation for `forces`.
+    fn divergence(&self, config: &Config, forces: &Grid<Vector2<f32>>) -> Grid<f32> {
+        let (width, height) = (forces.width(), forces.height());
+        Grid::par_new(width, height, |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            let f = *forces.get(x, y).unwrap();
+            0.5 * self
+                .stencil
+                .offsets(y)
+                .iter()
+                .map(|o| {
+                    let of = match config.boundaries.resolve(x + o.dx, y + o.dy, width, height) {
+                        Neighbor::Inside(nx, ny) => *forces.get(nx, ny).unwrap(),
+                        Neighbor::Outside(edge) if config.boundaries.get(edge).is_solid() => -f,
+                        Neighbor::Outside(_) => f,
+                    };
+                    of.dot(&o.displacement.normalize())
+                })
+                .sum::<f32>()
+        })
+    }
+}
+
+impl Projection {
+    /// The push on the tile at `(x, y)` from the pressure around it: the pressure gradient
+    /// divided by the tile's density, pointing down the gradient. Beyond a solid wall, the
+    /// pressure is whatever cancels the part of the tile's force across the wall, so that
+    /// nothing is left pushing tiles into walls or pulling them away.
+    pub fn push_on_xy(
+        &self,
+        x: isize,
+        y: isize,
+        config: &Config,
+        elements: &Grid<Tile>,
+    ) -> Vector2<f32> {
+        let (width, height) = (self.pressures.width(), self.pressures.height());
+        let p = *self.pressures.get(x, y).unwrap();
+        let f = *self.forces.get(x, y).unwrap();
+        let density = elements.get(x, y).unwrap().density(config).max(MIN_DENSITY);
+        let offsets = self.stencil.offsets(y);
+        let gradient = offsets
+            .iter()
+            .map(|o| {
+                let direction = o.displacement.normalize();
+                let op = match config.boundaries.resolve(x + o.dx, y + o.dy, width, height) {
+                    Neighbor::Inside(nx, ny) => *self.pressures.get(nx, ny).unwrap(),
+                    Neighbor::Outside(edge) if config.boundaries.get(edge).is_solid() => {
+                        p + density * f.dot(&direction)
+                    }
+                    Neighbor::Outside(_) => 0.0,
+                };
+                (op - p) * direction
+            })
+            .sum::<Vector2<f32>>()
+            * (2.0 / offsets.len().max(1) as f32);
+
+        -gradient / density
+    }
+}
+
+/// A coupling between a cell and a neighbor, or, without a neighbor, a boundary held at zero
+/// pressure.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    to: Option<(isize, isize)>,
+    weight: f32,
+}
+
+/// The equations for one grid of a multigrid hierarchy: for every cell,
+///
+/// ```text
+/// Σ weight * (p[to] - p) = r
+/// ```
+///
+/// over its links.
+#[derive(Debug, Clone)]
+struct Level {
+    links: Grid<Vec<Link>>,
+}
+
+impl Level {
+    /// The pressure at `(x, y)` that satisfies its equation given its neighbors' pressures.
+    fn relax(&self, pressures: &Grid<f32>, rhs: &Grid<f32>, x: isize, y: isize) -> f32 {
+        let links = self.links.get(x, y).unwrap();
+        let diagonal: f32 = links.iter().map(|l| l.weight).sum();
+        if diagonal <= 0.0 {
+            return *pressures.get(x, y).unwrap();
+        }
+        let neighbors: f32 = links
+            .iter()
+            .filter_map(|l| {
+                l.to.map(|(nx, ny)| l.weight * pressures.get(nx, ny).unwrap())
+            })
+            .sum();
+        (neighbors - rhs.get(x, y).unwrap()) / diagonal
+    }
+
+    fn residual(&self, pressures: &Grid<f32>, rhs: &Grid<f32>) -> Grid<f32> {
+        Grid::par_new(self.links.width(), self.links.height(), |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            let p = *pressures.get(x, y).unwrap();
+            let lhs: f32 = self
+                .links
+                .get(x, y)
+                .unwrap()
+                .iter()
+                .map(|l| {
+                    l.weight * (l.to.map_or(0.0, |(nx, ny)| *pressures.get(nx, ny).unwrap()) - p)
+                })
+                .sum();
+            rhs.get(x, y).unwrap() - lhs
+        })
+    }
+
+    fn largest_residual(&self, pressures: &Grid<f32>, rhs: &Grid<f32>) -> f32 {
+        self.residual(pressures, rhs)
+            .iter()
+            .fold(0.0, |max: f32, r| max.max(r.abs()))
+    }
+
+    fn jacobi(&self, pressures: &Grid<f32>, rhs: &Grid<f32>) -> Grid<f32> {
+        Grid::par_new(self.links.width(), self.links.height(), |x, y| {
+            let (x, y) = (x as isize, y as isize);
+            let p = *pressures.get(x, y).unwrap();
+            p + JACOBI_WEIGHT * (self.relax(pressures, rhs, x, y) - p)
+        })
+    }
+
+    /// Relaxes the cells with even `x + y`, then the rest. On a square lattice the cells of each
+    /// color only neighbor cells of the other, so every half is an exact Gauss-Seidel sweep. That
+    /// is not so on a hexagonal lattice, or across a periodic edge whose length is odd, where some
+    /// neighbors share a color: those relax from each other's old values, as in a Jacobi sweep.
+    fn red_black(&self, pressures: &Grid<f32>, rhs: &Grid<f32>) -> Grid<f32> {
+        let mut pressures = pressures.clone();
+        for color in [0, 1] {
+            pressures = Grid::par_new(self.links.width(), self.links.height(), |x, y| {
+                if (x + y) % 2 == color {
+                    self.relax(&pressures, rhs, x as isize, y as isize)
+                } else {
+                    *pressures.get(x as isize, y as isize).unwrap()
+                }
+            });
+        }
+        pressures
+    }
+
+    /// This level followed by ever coarser ones, until a side is at most two cells long.
+    fn hierarchy(self) -> Vec<Level> {
+        let mut levels = vec![self];
+        loop {
+            let finest = levels.last().unwrap();
+            if finest.links.width() <= 2 || finest.links.height() <= 2 {
+                return levels;
+            }
+            let coarser = finest.coarsen();
+            levels.push(coarser);
+        }
+    }
+
+    /// Merges every 2x2 block of cells into one, summing the links between blocks and dropping
+    /// the links within them.
+    fn coarsen(&self) -> Level {
+        let (width, height) = (
+            self.links.width().div_ceil(2),
+            self.links.height().div_ceil(2),
+        );
+        let mut links: Vec<Vec<Link>> = vec![vec![]; width * height];
+        for (x, y, fine) in self.links.enumerate() {
+            let block = &mut links[(y / 2) * width + x / 2];
+            for link in fine.iter() {
+                let to = link.to.map(|(nx, ny)| (nx / 2, ny / 2));
+                if to == Some(((x / 2) as isize, (y / 2) as isize)) {
+                    continue;
+                }
+                match block.iter_mut().find(|l| l.to == to) {
+                    Some(l) => l.weight += link.weight,
+                    None => block.push(Link {
+                        to,
+                        weight: link.weight,
+                    }),
+                }
+            }
+        }
+        Level {
+            links: Grid::from_cells(width, height, links),
+        }
+    }
+}
+
+/// Smooths `pressures` on the first level of `levels`, corrects it with the solution for its
+/// residual on the coarser levels, and smooths again.
+fn v_cycle(levels: &[Level], mut pressures: Grid<f32>, rhs: &Grid<f32>) -> Grid<f32> {
+    let (level, coarser) = levels.split_first().unwrap();
+    if coarser.is_empty() {
+        for _ in 0..COARSEST_SWEEPS {
+            pressures = level.red_black(&pressures, rhs);
+        }
+        return pressures;
+    }
+
+    for _ in 0..SMOOTHING_SWEEPS {
+        pressures = level.red_black(&pressures, rhs);
+    }
+    let residual = level.residual(&pressures, rhs);
+    let (width, height) = (coarser[0].links.width(), coarser[0].links.height());
+    let mut coarse_rhs = Grid::new(width, height, |_, _| 0.0);
+    for (x, y, r) in residual.enumerate() {
+        *coarse_rhs
+            .get_mut((x / 2) as isize, (y / 2) as isize)
+            .unwrap() += r;
+    }
+    let correction = v_cycle(coarser, Grid::new(width, height, |_, _| 0.0), &coarse_rhs);
+    for (x, y, p) in pressures.enumerate_mut() {
+        *p += correction.get((x / 2) as isize, (y / 2) as isize).unwrap();
+    }
+    for _ in 0..SMOOTHING_SWEEPS {
+        pressures = level.red_black(&pressures, rhs);
+    }
+    pressures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clamped_f32::ClampedF32, simulation::config::PressureSolver};
+
+    const METHODS: [PressureMethod; 3] = [
+        PressureMethod::Jacobi,
+        PressureMethod::RedBlackGaussSeidel,
+        PressureMethod::Multigrid,
+    ];
+
+    fn config(method: PressureMethod) -> Config {
+        Config {
+            pressure: PressureSolver {
+                method,
+                tolerance: ClampedF32::new(1e-4),
+                max_iterations: 10_000,
+            },
+            ..Config::default()
+        }
+    }
+
+    /// An 8x6 world under walls with air over water over soil, and forces that vary from cell to
+    /// cell.
+    fn layered(config: &Config) -> (ChunkedGrid<Tile>, Grid<Vector2<f32>>) {
+        let element = |y| match y {
+            0..=1 => "air",
+            2..=3 => "water",
+            _ => "soil",
+        };
+        let tiles = Grid::new(8, 6, |_, y| {
+            Tile::new(config.elements.lookup(element(y)).unwrap(), 0.5, 20.0)
+        });
+        let forces = Grid::new(8, 6, |x, y| {
+            Vector2::new(
+                ((3 * x + 5 * y) % 7) as f32 - 3.0,
+                2.0 + ((x + 2 * y) % 3) as f32,
+            )
+        });
+        (ChunkedGrid::new(tiles, 8), forces)
+    }
+
+    /// The pressure every method finds for the layered world, less its mean, along with the
+    /// largest residual left in its equations and the tolerance it should be within.
+    fn solve_layered(method: PressureMethod) -> (Grid<f32>, f32, f32) {
+        let config = config(method);
+        let (elements, forces) = layered(&config);
+        let system = PressureSystem::new(&config, &elements);
+        let divergence = system.divergence(&config, &forces);
+        let target = 1e-4 * divergence.iter().fold(0.0, |max: f32, d| max.max(d.abs()));
+        let pressures = system.solve(&config, forces).pressures;
+        let residual = system.levels[0].largest_residual(&pressures, &divergence);
+        let mean = pressures.iter().sum::<f32>() / (pressures.width() * pressures.height()) as f32;
+        (pressures.map(|p| p - mean), residual, target)
+    }
+
+    #[test]
+    fn every_method_solves_to_the_tolerance() {
+        for method in METHODS {
+            let (_, residual, target) = solve_layered(method);
+            assert!(residual <= target, "{method:?}: {residual} > {target}");
+        }
+    }
+
+    #[test]
+    fn the_methods_agree_up_to_a_constant() {
+        // Under walls all around, adding a constant to a solution gives another solution.
+        let (reference, _, _) = solve_layered(PressureMethod::Multigrid);
+        let scale = reference.iter().fold(0.0, |max: f32, p| max.max(p.abs()));
+        for method in [PressureMethod::Jacobi, PressureMethod::RedBlackGaussSeidel] {
+            let (pressures, _, _) = solve_layered(method);
+            let difference = pressures
+                .zip_with(&reference, |a, b| (a - b).abs())
+                .iter()
+                .fold(0.0, |max: f32, d| max.max(*d));
+            assert!(difference <= 1e-2 * scale, "{method:?}: {difference}");
+        }
+    }
+
+    #[test]
+    fn pressure_holds_up_a_still_water_column() {
+        for method in METHODS {
+            let config = config(method);
+            let water = config.elements.lookup("water").unwrap();
+            let elements = ChunkedGrid::new(Grid::new(3, 8, |_, _| Tile::new(water, 0.5, 20.0)), 8);
+            let gravity = Vector2::new(0.0, 10.0);
+            let projection =
+                PressureSystem::new(&config, &elements).solve(&config, elements.map(|_| gravity));
+
+            for (x, y, _) in elements.enumerate() {
+                let (x, y) = (x as isize, y as isize);
+                let net = gravity + projection.push_on_xy(x, y, &config, &elements);
+                assert!(net.norm() < 1e-2, "{method:?} at ({x}, {y}): {net}");
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::Element;
+
+/// Receives structured events as a [`State`](super::State) updates.
+pub trait SimulationObserver: Send {
+    fn on_event(&mut self, event: &SimulationEvent);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationEvent {
+    /// One force relaxation iteration finished. `residual` is the largest change in any tile's
+    /// force during the iteration.
+    ForceRelaxation { iteration: usize, residual: f32 },
+    /// Pressure across the world once force relaxation has finished.
+    Pressure { min: f32, max: f32, mean: f32 },
+    /// Move conflicts took `iterations` rounds to resolve, and `bids` bids when resolved by
+    /// auction. `alignment` is how well the resulting moves line up with the forces on the tiles
+    /// making them, in total.
+    ConflictResolution {
+        iterations: usize,
+        bids: usize,
+        alignment: f32,
+    },
+    /// `count` tiles ended the update in a different cell, including tiles that left the world.
+    TilesMoved { count: usize },
+    /// The tile at `(x, y)` transitioned from one element to another.
+    ElementTransition {
+        x: usize,
+        y: usize,
+        from: Element,
+        to: Element,
+    },
+    /// A phase of the update took `elapsed` to run.
+    PhaseTiming { phase: Phase, elapsed: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Forces,
+    Movement,
+    Inflow,
+    Saturation,
+    Absorption,
+    Temperature,
+    Elements,
+    /// The whole update.
+    Update,
+}
+
+/// The observers registered on a state. Clones of a state share its observers.
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Arc<Mutex<dyn SimulationObserver>>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: Arc<Mutex<dyn SimulationObserver>>) {
+        self.0.push(observer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn emit(&self, event: SimulationEvent) {
+        for observer in self.0.iter() {
+            observer
+                .lock()
+                .expect("Observer should not be poisoned")
+                .on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Observers").field(&self.0.len()).finish()
+    }
+}